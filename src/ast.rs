@@ -1,33 +1,81 @@
 
+use span::Span;
 use std::fmt;
 
+#[derive(Clone, Copy)]
 pub enum UnaryOp {
   BitNeg,
+  Negate,
 }
 
+#[derive(Clone, Copy)]
 pub enum BinOp {
   BitAnd,
   BitOr,
   BitXor,
   BitShLeft,
   BitShRight,
+  BitRotLeft,
+  BitRotRight,
   Plus,
   Minus,
   Times,
   Divide,
+  Modulo,
+  Power,
+  Eq,
+  NotEq,
+  Less,
+  LessEq,
+  Greater,
+  GreaterEq,
+  LogAnd,
+  LogOr,
 }
 
 #[derive(Debug)]
 pub enum Prog {
   Expression(Expr),
-  Assign(String, Expr),
+  /// Assign to a variable, giving it a layout if one is named: let x = e, or
+  /// let x: LAYOUT = e. The span covers the name and the layout.
+  Assign(String, Span, Option<String>, Expr),
+  /// Assign to some of the bits of a variable: let x[hi:lo] = e, or
+  /// let x[i] = e when there's no low bit. The span covers the name.
+  AssignSlice(String, Span, Expr, Option<Expr>, Expr),
+  Function(String, Vec<String>, Expr),
+  /// Define a layout with fields of these numbers of bits, from the lowest
+  /// bits up.
+  Layout(String, Vec<(String, u128)>),
 }
 
-pub enum Expr {
-  Const(u16),
+/// An expression, along with the span of the input it was parsed from.
+#[derive(Clone)]
+pub struct Expr {
+  pub kind: ExprKind,
+  pub span: Span,
+}
+
+#[derive(Clone)]
+pub enum ExprKind {
+  Const(u128),
+  /// A float, which can only be given to a conversion function: f32bits(1.5).
+  Float(f64),
   Var(String),
+  /// An earlier result: the last one if there's no number.
+  Result(Option<usize>),
+  /// Some of the bits of a word: x[hi:lo], or x[i] when there's no low bit.
+  Slice(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+  /// The bits of each expression one after the other, the first one highest:
+  /// {a, b, c}.
+  Concat(Vec<Expr>),
+  /// The bits of the expressions repeated a number of times: {n{a, b}}.
+  Replicate(Box<Expr>, Vec<Expr>),
+  /// A field of a variable with a layout: v.mode.
+  Field(Box<Expr>, String),
   BinaryOper(BinOp, Box<Expr>, Box<Expr>),
   UnaryOper(UnaryOp, Box<Expr>),
+  Call(String, Vec<Expr>),
+  Cond(Box<Expr>, Box<Expr>, Box<Expr>),
 }
 
 impl fmt::Debug for UnaryOp {
@@ -35,6 +83,7 @@ impl fmt::Debug for UnaryOp {
     use self::UnaryOp::*;
     write!(f, "{}", match *self {
       BitNeg => "!",
+      Negate => "-",
     })
   }
 }
@@ -48,27 +97,126 @@ impl fmt::Debug for BinOp {
       BitXor      => "^",
       BitShLeft   => "<<",
       BitShRight  => ">>",
+      BitRotLeft  => "<<<",
+      BitRotRight => ">>>",
       Plus        => "+",
       Minus       => "-",
       Times       => "*",
       Divide      => "/",
+      Modulo      => "%",
+      Power       => "**",
+      Eq          => "==",
+      NotEq       => "!=",
+      Less        => "<",
+      LessEq      => "<=",
+      Greater     => ">",
+      GreaterEq   => ">=",
+      LogAnd      => "&&",
+      LogOr       => "||",
     })
   }
 }
 
+impl Expr {
+  pub fn new(kind: ExprKind, span: Span) -> Expr {
+    Expr { kind, span }
+  }
+}
+
 impl fmt::Debug for Expr {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    use self::Expr::*;
+    write!(f, "{:?}", self.kind)
+  }
+}
+
+impl fmt::Debug for ExprKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use self::ExprKind::*;
     write!(f, "{}", match *self {
       Const(val)
           => format!("{}", val),
+      Float(val)
+          => format!("{:?}", val),
       Var(ref name)
           => name.to_string(),
+      Result(None)
+          => "_".to_string(),
+      Result(Some(n))
+          => format!("${}", n),
+      Slice(ref e, ref hi, Some(ref lo))
+          => format!("{:?}[{:?}:{:?}]", e, hi, lo),
+      Slice(ref e, ref i, None)
+          => format!("{:?}[{:?}]", e, i),
+      Concat(ref items)
+          => format!("{{{}}}", items.iter()
+                                    .map(|item| format!("{:?}", item))
+                                    .collect::<Vec<_>>()
+                                    .join(", ")),
+      Replicate(ref count, ref items)
+          => format!("{{{:?}{{{}}}}}", count, items.iter()
+                                                   .map(|item| format!("{:?}", item))
+                                                   .collect::<Vec<_>>()
+                                                   .join(", ")),
+      Field(ref e, ref field)
+          => format!("{:?}.{}", e, field),
       BinaryOper(ref op, ref e1, ref e2)
           => format!("({:?} {:?} {:?})", op, e1, e2),
       UnaryOper(ref op, ref e)
           => format!("({:?} {:?})", op, e),
+      Call(ref name, ref args)
+          => format!("{}({})", name, args.iter()
+                                         .map(|arg| format!("{:?}", arg))
+                                         .collect::<Vec<_>>()
+                                         .join(", ")),
+      Cond(ref c, ref e1, ref e2)
+          => format!("(? {:?} {:?} {:?})", c, e1, e2),
     })
   }
 }
 
+/// Expressions are written the way they'd be typed, with brackets around
+/// every operation so that they parse back to the same expression.
+impl fmt::Display for Expr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use self::ExprKind::*;
+    match self.kind {
+      Const(val)
+          => write!(f, "{}", val),
+      Float(val)
+          => write!(f, "{:?}", val),
+      Var(ref name)
+          => write!(f, "{}", name),
+      Result(None)
+          => write!(f, "_"),
+      Result(Some(n))
+          => write!(f, "${}", n),
+      Slice(ref e, ref hi, Some(ref lo))
+          => write!(f, "{}[{}:{}]", e, hi, lo),
+      Slice(ref e, ref i, None)
+          => write!(f, "{}[{}]", e, i),
+      Concat(ref items)
+          => write!(f, "{{{}}}", items.iter()
+                                      .map(|item| format!("{}", item))
+                                      .collect::<Vec<_>>()
+                                      .join(", ")),
+      Replicate(ref count, ref items)
+          => write!(f, "{{{}{{{}}}}}", count, items.iter()
+                                                   .map(|item| format!("{}", item))
+                                                   .collect::<Vec<_>>()
+                                                   .join(", ")),
+      Field(ref e, ref field)
+          => write!(f, "{}.{}", e, field),
+      BinaryOper(ref op, ref e1, ref e2)
+          => write!(f, "({} {:?} {})", e1, op, e2),
+      UnaryOper(ref op, ref e)
+          => write!(f, "({:?}{})", op, e),
+      Call(ref name, ref args)
+          => write!(f, "{}({})", name, args.iter()
+                                           .map(|arg| format!("{}", arg))
+                                           .collect::<Vec<_>>()
+                                           .join(", ")),
+      Cond(ref c, ref e1, ref e2)
+          => write!(f, "({} ? {} : {})", c, e1, e2),
+    }
+  }
+}
@@ -0,0 +1,162 @@
+
+use width::Width;
+
+/// A function that is always available, such as popcount.
+pub struct Builtin {
+  pub name: &'static str,
+  pub arity: usize,
+  pub doc: &'static str,
+  pub func: fn(Width, &[u128]) -> u128,
+}
+
+/// Every builtin function, in the order they are listed to the user.
+pub static BUILTINS: [Builtin; 10] = [
+  Builtin { name: "popcount", arity: 1, func: popcount,
+            doc: "Count the bits that are set." },
+  Builtin { name: "clz", arity: 1, func: clz,
+            doc: "Count the zero bits above the highest set bit." },
+  Builtin { name: "ctz", arity: 1, func: ctz,
+            doc: "Count the zero bits below the lowest set bit." },
+  Builtin { name: "bitrev", arity: 1, func: bitrev,
+            doc: "Reverse the order of the bits." },
+  Builtin { name: "bswap", arity: 1, func: bswap,
+            doc: "Reverse the order of the bytes." },
+  Builtin { name: "parity", arity: 1, func: parity,
+            doc: "1 if an odd number of bits are set, otherwise 0." },
+  Builtin { name: "lowbit", arity: 1, func: lowbit,
+            doc: "Keep only the lowest set bit." },
+  Builtin { name: "highbit", arity: 1, func: highbit,
+            doc: "Keep only the highest set bit." },
+  Builtin { name: "pext", arity: 2, func: pext,
+            doc: "pext(x, mask): gather the bits of x selected by mask into the low bits." },
+  Builtin { name: "pdep", arity: 2, func: pdep,
+            doc: "pdep(x, mask): scatter the low bits of x to the bits selected by mask." },
+];
+
+/// Find the builtin function with this name.
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+  BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+fn popcount(_: Width, args: &[u128]) -> u128 {
+  u128::from(args[0].count_ones())
+}
+
+fn clz(width: Width, args: &[u128]) -> u128 {
+  u128::from(args[0].leading_zeros() - (128 - width.bits()))
+}
+
+fn ctz(width: Width, args: &[u128]) -> u128 {
+  u128::from(args[0].trailing_zeros().min(width.bits()))
+}
+
+fn bitrev(width: Width, args: &[u128]) -> u128 {
+  args[0].reverse_bits() >> (128 - width.bits())
+}
+
+fn bswap(width: Width, args: &[u128]) -> u128 {
+  args[0].swap_bytes() >> (128 - width.bits())
+}
+
+fn parity(_: Width, args: &[u128]) -> u128 {
+  u128::from(args[0].count_ones() & 1)
+}
+
+fn lowbit(_: Width, args: &[u128]) -> u128 {
+  args[0] & args[0].wrapping_neg()
+}
+
+fn highbit(_: Width, args: &[u128]) -> u128 {
+  match args[0] {
+    0 => 0,
+    x => 1 << (127 - x.leading_zeros()),
+  }
+}
+
+fn pext(_: Width, args: &[u128]) -> u128 {
+  let (x, mask) = (args[0], args[1]);
+  let mut result = 0;
+  let mut k = 0;
+  for i in 0..128 {
+    if (mask >> i) & 1 == 1 {
+      result |= ((x >> i) & 1) << k;
+      k += 1;
+    }
+  }
+  result
+}
+
+fn pdep(_: Width, args: &[u128]) -> u128 {
+  let (x, mask) = (args[0], args[1]);
+  let mut result = 0;
+  let mut k = 0;
+  for i in 0..128 {
+    if (mask >> i) & 1 == 1 {
+      result |= ((x >> k) & 1) << i;
+      k += 1;
+    }
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use width::WIDTHS;
+
+  fn call(name: &str, bits: u32, args: &[u128]) -> u128 {
+    let width = Width::new(bits).unwrap();
+    width.truncate((lookup(name).unwrap().func)(width, args))
+  }
+
+  #[test]
+  fn known_values() {
+    assert_eq!(call("bswap", 16, &[0x1234]), 0x3412);
+    assert_eq!(call("bswap", 32, &[0x12345678]), 0x78563412);
+    assert_eq!(call("bswap", 8, &[0x12]), 0x12);
+    assert_eq!(call("bitrev", 8, &[0b0000_0110]), 0b0110_0000);
+    assert_eq!(call("clz", 16, &[0x00ff]), 8);
+    assert_eq!(call("ctz", 16, &[0x0f00]), 8);
+    assert_eq!(call("highbit", 16, &[0x0f00]), 0x0800);
+    assert_eq!(call("lowbit", 16, &[0x0f00]), 0x0100);
+    assert_eq!(call("popcount", 16, &[0xf0f0]), 8);
+    assert_eq!(call("parity", 16, &[0b111]), 1);
+    assert_eq!(call("pext", 16, &[0b1011_0110, 0b1111_0000]), 0b1011);
+    assert_eq!(call("pdep", 16, &[0b1011, 0b1111_0000]), 0b1011_0000);
+  }
+
+  #[test]
+  fn every_width() {
+    for &bits in WIDTHS.iter() {
+      let top = 1 << (bits - 1);
+      let mask = Width::new(bits).unwrap().mask();
+      assert_eq!(call("clz", bits, &[0]), u128::from(bits));
+      assert_eq!(call("clz", bits, &[1]), u128::from(bits - 1));
+      assert_eq!(call("clz", bits, &[top]), 0);
+      assert_eq!(call("ctz", bits, &[0]), u128::from(bits));
+      assert_eq!(call("ctz", bits, &[top]), u128::from(bits - 1));
+      assert_eq!(call("bitrev", bits, &[1]), top);
+      assert_eq!(call("bitrev", bits, &[top]), 1);
+      assert_eq!(call("bswap", bits, &[0xab]), 0xab << (bits - 8));
+      assert_eq!(call("bswap", bits, &[0xab << (bits - 8)]), 0xab);
+      assert_eq!(call("highbit", bits, &[mask]), top);
+      assert_eq!(call("lowbit", bits, &[mask]), 1);
+      assert_eq!(call("pext", bits, &[mask, top | 1]), 0b11);
+      assert_eq!(call("pdep", bits, &[0b11, top | 1]), top | 1);
+    }
+  }
+
+  #[test]
+  fn pext_and_pdep_are_inverses() {
+    let masks = [0xf0f0, 0x8001, 0x0ff0, 0xffff, 0];
+    for &mask in masks.iter() {
+      for &x in [0x1234, 0xffff, 0xa5a5, 0].iter() {
+        let packed = call("pext", 16, &[x, mask]);
+        assert_eq!(call("pdep", 16, &[packed, mask]), x & mask);
+        let low = (1 << call("popcount", 16, &[mask])) - 1;
+        assert_eq!(call("pext", 16, &[call("pdep", 16, &[x, mask]), mask]), x & low);
+      }
+    }
+  }
+
+}
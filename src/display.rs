@@ -0,0 +1,270 @@
+
+use float::FloatFormat;
+use layout::Layout;
+use std::fmt;
+use width::Width;
+
+/// The ways a word can be written out.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+  Binary,
+  Hex,
+  Octal,
+  Decimal,
+  Signed,
+  Char,
+  /// The sign, exponent and mantissa of an IEEE-754 float in the low bits.
+  Float(FloatFormat),
+}
+
+/// How the digits of a binary number are split into groups.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Grouping {
+  NoGroups,
+  Nibbles,
+  Bytes,
+}
+
+impl Format {
+
+  /// Parse the name of a format, e.g. "hex".
+  pub fn parse(s: &str) -> Option<Format> {
+    use self::Format::*;
+    match s.trim() {
+      "bin" | "binary"  => Some(Binary),
+      "hex"             => Some(Hex),
+      "oct" | "octal"   => Some(Octal),
+      "dec" | "decimal" => Some(Decimal),
+      "signed"          => Some(Signed),
+      "char"            => Some(Char),
+      name              => FloatFormat::parse(name).map(Float),
+    }
+  }
+
+}
+
+impl Grouping {
+
+  /// Parse the name of a grouping, e.g. "nibble".
+  pub fn parse(s: &str) -> Option<Grouping> {
+    use self::Grouping::*;
+    match s.trim() {
+      "none"                => Some(NoGroups),
+      "nibble" | "nibbles"  => Some(Nibbles),
+      "byte" | "bytes"      => Some(Bytes),
+      _ => None,
+    }
+  }
+
+  /// The number of binary digits in each group.
+  fn size(&self) -> Option<u32> {
+    use self::Grouping::*;
+    match *self {
+      NoGroups  => None,
+      Nibbles   => Some(4),
+      Bytes     => Some(8),
+    }
+  }
+
+}
+
+impl fmt::Display for Format {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use self::Format::*;
+    write!(f, "{}", match *self {
+      Binary  => "binary",
+      Hex     => "hex",
+      Octal   => "octal",
+      Decimal => "decimal",
+      Signed  => "signed",
+      Char    => "char",
+      Float(ref format) => format.name,
+    })
+  }
+}
+
+impl fmt::Display for Grouping {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use self::Grouping::*;
+    write!(f, "{}", match *self {
+      NoGroups  => "none",
+      Nibbles   => "nibble",
+      Bytes     => "byte",
+    })
+  }
+}
+
+/// Settings controlling how words are shown to the user.
+#[derive(Clone, Copy, Debug)]
+pub struct Display {
+  pub format: Format,
+  pub grouping: Grouping,
+  pub ruler: bool,
+}
+
+impl Default for Display {
+  fn default() -> Display {
+    Display {
+      format: Format::Binary,
+      grouping: Grouping::NoGroups,
+      ruler: false,
+    }
+  }
+}
+
+impl Display {
+
+  /// Show a word in the chosen format. Apart from the decimal formats, the
+  /// decimal value follows in brackets. In signed mode both the unsigned and
+  /// signed decimal are given.
+  pub fn show(&self, x: u128, width: Width, signed: bool) -> String {
+    use self::Format::*;
+    let decimal = if signed {
+      format!("({}, signed {})", x, width.sign_extend(x))
+    } else {
+      format!("({})", x)
+    };
+    match self.format {
+      Binary  => format!("{} {}", self.binary_string(x, width), decimal),
+      Hex     => format!("{} {}", as_hex_string(x, width), decimal),
+      Octal   => format!("0o{:o} {}", x, decimal),
+      Decimal => format!("{}", x),
+      Signed  => format!("{}", width.sign_extend(x)),
+      Char    => format!("{} {}", as_char_string(x), decimal),
+      Float(format) => as_float_string(x, width, format),
+    }
+  }
+
+  /// Produce the string of 1s and 0s representing this number in binary,
+  /// split into groups of digits if grouping is on.
+  pub fn binary_string(&self, x: u128, width: Width) -> String {
+    let mut s = String::with_capacity(2 * width.bits() as usize);
+    for i in (0..width.bits()).rev() {
+      let digit = (x >> i) & 1;
+      s.push_str(&format!("{}", digit));
+      if self.ends_group(i) {
+        s.push(' ');
+      }
+    }
+    s
+  }
+
+  /// Produce a line numbering the bits of a word, which lines up with the
+  /// digits produced by binary_string. Returns nothing unless the ruler is
+  /// on and words are shown in binary.
+  pub fn ruler(&self, width: Width) -> Option<String> {
+    if !self.ruler || self.format != Format::Binary {
+      return None;
+    }
+    let step = self.grouping.size().unwrap_or(4);
+    let mut ruler = String::new();
+    for i in (0..width.bits()).rev() {
+      if i % step != step - 1 && i != 0 {
+        continue;
+      }
+      
+      // Each label starts above its bit, unless it would run into the label
+      // before it.
+      let column = self.column(i, width);
+      if ruler.is_empty() || ruler.len() < column {
+        ruler.push_str(&" ".repeat(column - ruler.len()));
+        ruler.push_str(&format!("{}", i));
+      }
+    }
+    Some(ruler)
+  }
+
+  /// Check if a group of binary digits ends after bit i.
+  fn ends_group(&self, i: u32) -> bool {
+    match self.grouping.size() {
+      Some(size) => i != 0 && i.is_multiple_of(size),
+      None => false,
+    }
+  }
+
+  /// Decode a word with a layout, producing rows of labels above the word in
+  /// binary, grouped like the output of binary_string. Each field is labelled
+  /// with its name and value, starting above its highest bit. Labels which
+  /// would run into the one to their right go on a higher row, with a '|'
+  /// leading down to their bit. Any bits above the layout's fields have no
+  /// label.
+  pub fn decode(&self, x: u128, width: Width, layout: &Layout) -> Vec<String> {
+    
+    // Place the labels from the right, so that each label only has to stay
+    // clear of the labels in its row, and the lines leading down from the
+    // rows above, which are all to its right.
+    let mut rows: Vec<Vec<(usize, String)>> = Vec::new();
+    for field in layout.fields.iter() {
+      let column = self.column(field.hi(), width);
+      let label = format!("{}={}", field.name, field.extract(x));
+      let end = column + label.len();
+      let fits = |row: usize| rows[row..].iter().all(|row| row.last().is_none_or(|&(start, _)| end < start));
+      match (0..rows.len()).find(|&row| fits(row)) {
+        Some(row) => rows[row].push((column, label)),
+        None => rows.push(vec![(column, label)]),
+      }
+    }
+    
+    let mut lines = Vec::new();
+    for (i, row) in rows.iter().enumerate().rev() {
+      let mut cells: Vec<(usize, &str)> = row.iter().map(|&(column, ref label)| (column, label.as_str()))
+                                             .collect();
+      for higher in &rows[i + 1..] {
+        cells.extend(higher.iter().map(|&(column, _)| (column, "|")));
+      }
+      cells.sort_by_key(|&(column, _)| column);
+      let mut line = String::new();
+      for (column, cell) in cells {
+        line.push_str(&" ".repeat(column - line.len()));
+        line.push_str(cell);
+      }
+      lines.push(line);
+    }
+    lines.push(self.binary_string(x, width));
+    lines
+  }
+
+  /// The column that bit i appears in, in the output of binary_string.
+  fn column(&self, i: u32, width: Width) -> usize {
+    let digits = width.bits() - 1 - i;
+    let separators = match self.grouping.size() {
+      Some(size) => digits / size,
+      None => 0,
+    };
+    (digits + separators) as usize
+  }
+
+}
+
+/// Produce the lowest bits of a number as 1s and 0s.
+fn binary_digits(x: u128, bits: u32) -> String {
+  (0..bits).rev().map(|i| if (x >> i) & 1 == 1 { '1' } else { '0' }).collect()
+}
+
+/// Produce the hex digits of this number, padded to the width of the word.
+fn as_hex_string(x: u128, width: Width) -> String {
+  let digits = (width.bits() / 4) as usize;
+  format!("0x{:0digits$x}", x, digits = digits)
+}
+
+/// Split the low bits of a word into the sign, exponent and mantissa of a
+/// float, followed by the float's value in brackets.
+fn as_float_string(x: u128, width: Width, format: FloatFormat) -> String {
+  if width.bits() < format.bits() {
+    return format!("<{} needs {} bits>", format, format.bits());
+  }
+  let parts = format.parts(x);
+  format!("{} {} {} ({})", parts.sign, binary_digits(parts.exp, format.exp_bits),
+          binary_digits(parts.man, format.man_bits), format.describe(x))
+}
+
+/// Produce the character with this code point, quoted and escaped.
+fn as_char_string(x: u128) -> String {
+  if x > u128::from(u32::MAX) {
+    return "<not a char>".to_string();
+  }
+  match ::std::char::from_u32(x as u32) {
+    Some(ch) => format!("{:?}", ch),
+    None => "<not a char>".to_string(),
+  }
+}
@@ -0,0 +1,138 @@
+
+use bitshift::builtins::BUILTINS;
+use bitshift::float::CONVERSIONS;
+use bitshift::Context;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Config, Editor, Helper};
+use std::env;
+use std::path::PathBuf;
+
+/// Words that can always be completed, besides the names of builtins.
+const KEYWORDS: [&str; 4] = ["let", "fn", "layout", "exit"];
+
+/// Where history is kept between sessions.
+fn history_path() -> Option<PathBuf> {
+  env::var_os("HOME").map(|home| PathBuf::from(home).join(".bitshift_history"))
+}
+
+/// Completes the word before the cursor with the name of a variable, function,
+/// layout or keyword.
+#[derive(Default)]
+struct Completions {
+  names: Vec<String>,
+}
+
+impl Completions {
+
+  /// Refresh the names that can be completed, since the user may have
+  /// defined new variables and functions.
+  fn update(&mut self, ctx: &Context) {
+    self.names.clear();
+    self.names.extend(KEYWORDS.iter().map(|kw| kw.to_string()));
+    self.names.extend(BUILTINS.iter().map(|builtin| builtin.name.to_string()));
+    self.names.extend(CONVERSIONS.iter().map(|conversion| conversion.name.to_string()));
+    self.names.extend(ctx.variables().into_iter().map(|(name, _)| name.clone()));
+    self.names.extend(ctx.functions().into_iter().map(|(name, _)| name.clone()));
+    self.names.extend(ctx.layouts().into_iter().map(|layout| layout.name.clone()));
+    self.names.sort();
+    self.names.dedup();
+  }
+
+}
+
+impl Completer for Completions {
+  type Candidate = String;
+
+  fn complete(&self, line: &str, pos: usize, _: &rustyline::Context)
+      -> rustyline::Result<(usize, Vec<String>)> {
+    let start = line[..pos].char_indices()
+      .rev()
+      .take_while(|&(_, ch)| ch.is_alphanumeric() || ch == '_')
+      .last()
+      .map_or(pos, |(i, _)| i);
+    let word = &line[start..pos];
+    if word.is_empty() {
+      return Ok((pos, Vec::new()));
+    }
+    let matches = self.names.iter().filter(|name| name.starts_with(word)).cloned().collect();
+    Ok((start, matches))
+  }
+}
+
+impl Hinter for Completions {
+  type Hint = String;
+}
+
+impl Highlighter for Completions {}
+
+impl Validator for Completions {}
+
+impl Helper for Completions {}
+
+/// Reads lines from the terminal, with arrow-key editing, history that is
+/// kept between sessions, reverse search (Ctrl-R) and tab completion.
+pub struct LineEditor {
+  editor: Editor<Completions, DefaultHistory>,
+  history: Option<PathBuf>,
+}
+
+impl LineEditor {
+
+  /// Start editing, loading the history from previous sessions if there is
+  /// any.
+  pub fn new() -> Result<LineEditor, ReadlineError> {
+    let config = Config::builder()
+      .auto_add_history(true)
+      .history_ignore_dups(true)?
+      .history_ignore_space(true)
+      .build();
+    let mut editor = Editor::with_config(config)?;
+    editor.set_helper(Some(Completions::default()));
+    let history = history_path();
+    if let Some(ref path) = history {
+      // There's no history the first time the calculator is used.
+      let _ = editor.load_history(path);
+    }
+    Ok(LineEditor { editor, history })
+  }
+
+  /// Read the next line, completing names from the context. Returns nothing
+  /// when there is no more input.
+  pub fn read_line(&mut self, prompt: &str, ctx: &Context) -> Option<String> {
+    if let Some(completions) = self.editor.helper_mut() {
+      completions.update(ctx);
+    }
+    loop {
+      match self.editor.readline(prompt) {
+        Ok(line) => return Some(line),
+        // Ctrl-C throws away the line being edited.
+        Err(ReadlineError::Interrupted) => continue,
+        Err(ReadlineError::Eof) => return None,
+        Err(e) => {
+          println!("{}", e);
+          return None;
+        },
+      }
+    }
+  }
+
+  /// Every line in the history, oldest first.
+  pub fn history(&self) -> Vec<String> {
+    self.editor.history().iter().cloned().collect()
+  }
+
+  /// Save the history so it can be used in later sessions.
+  pub fn save_history(&mut self) {
+    if let Some(ref path) = self.history {
+      if let Err(e) = self.editor.save_history(path) {
+        println!("Couldn't save history to {}: {}", path.display(), e);
+      }
+    }
+  }
+
+}
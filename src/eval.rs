@@ -1,13 +1,21 @@
 
-use ast::Expr;
-use ast::Prog;
+use ast::{BinOp, Expr, ExprKind, Prog, UnaryOp};
+use builtins;
+use float::{self, Conversion};
+use layout::{Field, Layout};
+use span::Span;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::mem;
+use std::rc::Rc;
+use width::Width;
 
 #[derive(Debug)]
 pub struct EvalError {
   msg: String,
+  span: Option<Span>,
 }
 
 macro_rules! err {
@@ -18,8 +26,20 @@ impl EvalError {
   fn new(msg:&str) -> EvalError {
     EvalError {
       msg: msg.to_string(),
+      span: None,
     }
   }
+  
+  /// The part of the input where the error happened, if it's known.
+  pub fn span(&self) -> Option<Span> {
+    self.span
+  }
+  
+  /// Say where the error happened, unless that is already known.
+  fn at(mut self, span: Span) -> EvalError {
+    self.span = self.span.or(Some(span));
+    self
+  }
 }
 
 impl fmt::Display for EvalError {
@@ -36,81 +56,921 @@ impl Error for EvalError {
 
 type EvalResult<T> = Result<T, EvalError>;
 
-/// A context tracks what value a variable is bound to.
+/// What to do when the result of an arithmetic operation doesn't fit in a word.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Overflow {
+  /// Discard the bits that don't fit.
+  Wrapping,
+  /// Report an error.
+  Checked,
+  /// Clamp the result to the largest or smallest value that fits.
+  Saturating,
+}
+
+impl Overflow {
+
+  /// Parse the name of an overflow policy, e.g. "checked".
+  pub fn parse(s: &str) -> Option<Overflow> {
+    match s.trim() {
+      "wrapping" | "wrap"       => Some(Overflow::Wrapping),
+      "checked" | "check"       => Some(Overflow::Checked),
+      "saturating" | "saturate" => Some(Overflow::Saturating),
+      _ => None,
+    }
+  }
+
+}
+
+impl fmt::Display for Overflow {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", match *self {
+      Overflow::Wrapping    => "wrapping",
+      Overflow::Checked     => "checked",
+      Overflow::Saturating  => "saturating",
+    })
+  }
+}
+
+/// One operation performed while evaluating an expression, along with its
+/// operands and result.
+pub enum Step {
+  Unary(UnaryOp, u128, u128),
+  Binary(BinOp, u128, u128, u128),
+  /// A && or || whose result was settled by its left operand, so the right
+  /// operand was never evaluated.
+  Settled(BinOp, u128, u128),
+  /// A conditional, with the value of its condition and the value of the
+  /// branch it picked.
+  Cond(u128, u128),
+  /// A call to a builtin function, with its name, arguments and result.
+  Call(&'static str, Vec<u128>, u128),
+}
+
+/// A function defined by the user, e.g. fn roundup8(x) = (x + 7) & !7.
+pub struct Function {
+  pub params: Vec<String>,
+  pub body: Expr,
+}
+
+/// The arguments of a call to a user-defined function which is currently
+/// being evaluated.
+struct Frame {
+  name: String,
+  locals: HashMap<String, u128>,
+}
+
+/// A context tracks what value a variable is bound to, which functions and
+/// layouts the user has defined, and the results remembered so far. It also
+/// tracks the width of the words being operated on, whether those words are
+/// signed, and what happens when arithmetic overflows. When tracing is on it
+/// also records each step of the evaluation.
 pub struct Context {
-  vars: HashMap<String, u16>,
+  vars: HashMap<String, u128>,
+  funcs: HashMap<String, Rc<Function>>,
+  layouts: HashMap<String, Layout>,
+  /// The name of the layout each variable has, if it has one.
+  var_layouts: HashMap<String, String>,
+  results: Vec<u128>,
+  frames: Vec<Frame>,
+  width: Width,
+  signed: bool,
+  overflow: Overflow,
+  trace: Option<Vec<Step>>,
 }
 
 impl Context {
-  pub fn new() -> Context {
+  pub fn new(width: Width, signed: bool) -> Context {
     Context {
       vars: HashMap::new(),
+      funcs: HashMap::new(),
+      layouts: HashMap::new(),
+      var_layouts: HashMap::new(),
+      results: Vec::new(),
+      frames: Vec::new(),
+      width,
+      signed,
+      overflow: Overflow::Wrapping,
+      trace: None,
     }
   }
 }
 
 impl Context {
 
-  pub fn insert(&mut self, var: &str, val: u16) {
+  pub fn insert(&mut self, var: &str, val: u128) {
     self.vars.insert(var.to_string(), val);
   }
   
-  pub fn lookup(&self, var: &str) -> EvalResult<u16> {
+  /// Forget the variable, function or layout with this name. Returns whether
+  /// there was one.
+  pub fn unset(&mut self, name: &str) -> bool {
+    let var = self.vars.remove(name).is_some();
+    self.var_layouts.remove(name);
+    let func = self.funcs.remove(name).is_some();
+    let layout = self.layouts.remove(name).is_some();
+    var || func || layout
+  }
+  
+  /// Forget every variable, function and layout.
+  pub fn clear(&mut self) {
+    self.vars.clear();
+    self.var_layouts.clear();
+    self.funcs.clear();
+    self.layouts.clear();
+  }
+  
+  /// Look up the value of a variable. Inside a function, its parameters
+  /// shadow any variables with the same name.
+  pub fn lookup(&self, var: &str) -> EvalResult<u128> {
+    if let Some(frame) = self.frames.last() {
+      if let Some(&val) = frame.locals.get(var) {
+        return Ok(val);
+      }
+    }
     match self.vars.get(var) {
       Some(&ch) => Ok(ch),
       None => err!(&format!("Variable '{}' not found.", var)),
     }
   }
   
+  /// Remember a result so that later expressions can refer to it. Returns
+  /// its number, counting from 1.
+  pub fn remember(&mut self, val: u128) -> usize {
+    self.results.push(val);
+    self.results.len()
+  }
+  
+  /// Look up an earlier result by its number, or the last result if there's
+  /// no number.
+  pub fn result(&self, n: Option<usize>) -> EvalResult<u128> {
+    let found = match n {
+      Some(n) => n.checked_sub(1).and_then(|i| self.results.get(i)),
+      None => self.results.last(),
+    };
+    match (found, n) {
+      (Some(&val), _) => Ok(val),
+      (None, Some(n)) => err!(&format!("There's no result ${}. There are {} so far.", n, self.results.len())),
+      (None, None) => err!("There are no results yet."),
+    }
+  }
+  
+  /// Define a function, replacing any function with the same name. Builtin
+  /// functions and float conversions can't be replaced.
+  pub fn define(&mut self, name: &str, params: Vec<String>, body: Expr) -> EvalResult<()> {
+    if builtins::lookup(name).is_some() || float::lookup(name).is_some() {
+      return err!(&format!("Can't redefine builtin function '{}'.", name));
+    }
+    self.funcs.insert(name.to_string(), Rc::new(Function { params, body }));
+    Ok(())
+  }
+  
+  /// Define a layout, replacing any layout with the same name. Variables
+  /// with the old layout lose it if the new one doesn't fit in the word.
+  pub fn define_layout(&mut self, name: &str, fields: &[(String, u128)]) -> EvalResult<()> {
+    match Layout::new(name, fields) {
+      Ok(layout) => {
+        self.layouts.insert(name.to_string(), layout);
+        self.drop_misfit_layouts();
+        Ok(())
+      },
+      Err(msg) => err!(&msg),
+    }
+  }
+  
+  /// Find the layout with this name.
+  pub fn layout(&self, name: &str) -> Option<&Layout> {
+    self.layouts.get(name)
+  }
+  
+  /// Every layout the user has defined, sorted by name.
+  pub fn layouts(&self) -> Vec<&Layout> {
+    let mut layouts: Vec<_> = self.layouts.values().collect();
+    layouts.sort_by_key(|layout| &layout.name);
+    layouts
+  }
+  
+  /// Give a variable a layout, which has to fit in the word.
+  pub fn set_layout(&mut self, var: &str, layout: &str) -> EvalResult<()> {
+    match self.layouts.get(layout) {
+      Some(found) => if let Err(msg) = found.fits(self.width) {
+        return err!(&msg);
+      },
+      None => return err!(&format!("Layout '{}' not found.", layout)),
+    }
+    self.var_layouts.insert(var.to_string(), layout.to_string());
+    Ok(())
+  }
+  
+  /// Find the layout of a variable, if it has one. Inside a function, its
+  /// parameters don't have layouts.
+  pub fn layout_of(&self, var: &str) -> Option<&Layout> {
+    if let Some(frame) = self.frames.last() {
+      if frame.locals.contains_key(var) {
+        return None;
+      }
+    }
+    self.var_layouts.get(var).and_then(|layout| self.layouts.get(layout))
+  }
+  
+  /// Every variable and its value, sorted by name.
+  pub fn variables(&self) -> Vec<(&String, u128)> {
+    let mut vars: Vec<_> = self.vars.iter().map(|(name, &val)| (name, val)).collect();
+    vars.sort_by_key(|&(name, _)| name);
+    vars
+  }
+  
+  /// Every function the user has defined, sorted by name.
+  pub fn functions(&self) -> Vec<(&String, &Function)> {
+    let mut funcs: Vec<_> = self.funcs.iter().map(|(name, func)| (name, &**func)).collect();
+    funcs.sort_by_key(|&(name, _)| name);
+    funcs
+  }
+  
+  pub fn width(&self) -> Width {
+    self.width
+  }
+  
+  /// Change the word width. Variables are truncated to fit the new width,
+  /// and lose their layouts if those don't fit any more.
+  pub fn set_width(&mut self, width: Width) {
+    self.width = width;
+    for val in self.vars.values_mut().chain(self.results.iter_mut()) {
+      *val = width.truncate(*val);
+    }
+    self.drop_misfit_layouts();
+  }
+  
+  /// Take away the layouts of variables whose layouts don't fit in the word.
+  fn drop_misfit_layouts(&mut self) {
+    let (width, layouts) = (self.width, &self.layouts);
+    self.var_layouts.retain(|_, layout| {
+      layouts.get(layout).is_none_or(|layout| layout.fits(width).is_ok())
+    });
+  }
+  
+  /// Check if words are interpreted as two's complement signed integers.
+  pub fn signed(&self) -> bool {
+    self.signed
+  }
+  
+  pub fn set_signed(&mut self, signed: bool) {
+    self.signed = signed;
+  }
+  
+  pub fn overflow(&self) -> Overflow {
+    self.overflow
+  }
+  
+  pub fn set_overflow(&mut self, overflow: Overflow) {
+    self.overflow = overflow;
+  }
+  
+  /// Whether the steps of each evaluation are being recorded.
+  pub fn tracing(&self) -> bool {
+    self.trace.is_some()
+  }
+  
+  /// Start or stop recording the steps of each evaluation.
+  pub fn set_tracing(&mut self, tracing: bool) {
+    self.trace = if tracing { Some(Vec::new()) } else { None };
+  }
+  
+  /// Take the steps recorded since this was last called. Returns nothing if
+  /// tracing is off.
+  pub fn take_steps(&mut self) -> Vec<Step> {
+    match self.trace {
+      Some(ref mut steps) => mem::take(steps),
+      None => Vec::new(),
+    }
+  }
+  
+  /// Record a step of the evaluation, if tracing is on.
+  fn record(&mut self, step: Step) {
+    if let Some(ref mut steps) = self.trace {
+      steps.push(step);
+    }
+  }
+  
 }
 
-pub fn eval(ctx: &mut Context, prog: &Prog) -> EvalResult<u16> {
+/// Evaluate a program. Returns the value of the program, if it has one.
+pub fn eval(ctx: &mut Context, prog: &Prog) -> EvalResult<Option<u128>> {
+  if let Some(ref mut steps) = ctx.trace {
+    steps.clear();
+  }
   match *prog {
     Prog::Expression(ref expr) => {
       let v = eval_expr(ctx, expr)?;
-      Ok(v)
+      Ok(Some(v))
     },
-    Prog::Assign(ref name, ref expr) => {
+    Prog::Assign(ref name, span, ref layout, ref expr) => {
       let v = eval_expr(ctx, expr)?;
+      if let Some(ref layout) = *layout {
+        ctx.set_layout(name, layout).map_err(|e| e.at(span))?;
+      }
       ctx.insert(name, v);
-      Ok(v)
+      Ok(Some(v))
+    },
+    Prog::AssignSlice(ref name, span, ref hi, ref lo, ref expr) => {
+      let old = ctx.lookup(name).map_err(|e| e.at(span))?;
+      let (hi, lo) = eval_bounds(ctx, hi, lo.as_ref())?;
+      let v = eval_expr(ctx, expr)?;
+      
+      // The value has to fit in the bits being assigned, either as an
+      // unsigned number, or as a signed one in signed mode.
+      let size = hi - lo + 1;
+      let mask = slice_mask(size);
+      let signed = ctx.width.sign_extend(v);
+      let fits = v & !mask == 0 || ctx.signed && (signed << (128 - size)) >> (128 - size) == signed;
+      if !fits {
+        let v = if ctx.signed { signed.to_string() } else { v.to_string() };
+        let msg = format!("{} doesn't fit in the {} bit(s) of {}[{}:{}].", v, size, name, hi, lo);
+        return Err(EvalError::new(&msg).at(expr.span));
+      }
+      let v = (old & !(mask << lo)) | ((v & mask) << lo);
+      ctx.insert(name, v);
+      Ok(Some(v))
+    },
+    Prog::Function(ref name, ref params, ref body) => {
+      ctx.define(name, params.clone(), body.clone())?;
+      Ok(None)
+    },
+    Prog::Layout(ref name, ref fields) => {
+      ctx.define_layout(name, fields)?;
+      Ok(None)
     },
   }
 }
 
-pub fn eval_expr(ctx: &mut Context, expr: &Expr) -> EvalResult<u16> {
-  use self::Expr::*;
+/// Evaluate an expression. If evaluation fails, the error points at the
+/// smallest expression which failed.
+pub fn eval_expr(ctx: &mut Context, expr: &Expr) -> EvalResult<u128> {
+  eval_kind(ctx, &expr.kind).map_err(|e| e.at(expr.span))
+}
+
+fn eval_kind(ctx: &mut Context, expr: &ExprKind) -> EvalResult<u128> {
+  use ast::ExprKind::*;
   match *expr {
   
-    Const(val) => Ok(val),
+    Const(val) => {
+      if ctx.width.fits(val) {
+        Ok(val)
+      } else {
+        err!(&format!("Number {} doesn't fit in {} bits.", val, ctx.width))
+      }
+    },
+    
+    Float(val) => err!(&format!("A float like {:?} can only be given to a conversion function, \
+                                 such as f32bits({:?}).", val, val)),
     
     Var(ref name) => Ok(ctx.lookup(name)?),
+    
+    Result(n) => ctx.result(n),
+    
+    Slice(ref e, ref hi, ref lo) => {
+      let e = eval_expr(ctx, e)?;
+      let (hi, lo) = eval_bounds(ctx, hi, lo.as_deref())?;
+      Ok((e >> lo) & slice_mask(hi - lo + 1))
+    },
+    
+    Concat(ref items) => Ok(eval_concat(ctx, items)?.0),
+    
+    Replicate(ref count, ref items) => Ok(eval_replicate(ctx, count, items)?.0),
+    
+    Field(ref e, ref name) => {
+      let field = field_of(ctx, e, name)?;
+      Ok(field.extract(eval_expr(ctx, e)?))
+    },
                
-    BinaryOper(ref op, ref e1, ref e2) => {
+    BinaryOper(op, ref e1, ref e2) => {
       use ast::BinOp::*;
       let e1 = eval_expr(ctx, e1)?;
+      
+      // The right operand of && and || is only evaluated if it's needed.
+      let settled = match op {
+        LogAnd if e1 == 0 => Some(0),
+        LogOr if e1 != 0 => Some(1),
+        _ => None,
+      };
+      if let Some(result) = settled {
+        ctx.record(Step::Settled(op, e1, result));
+        return Ok(result);
+      }
+      
       let e2 = eval_expr(ctx, e2)?;
-      let result = match *op {
+      let width = ctx.width;
+      match op {
+        BitShLeft | BitShRight if e2 >= u128::from(width.bits()) =>
+          return err!(&format!("Can't shift a {}-bit word by {} bits.", width, e2)),
+        Divide | Modulo if e2 == 0 =>
+          return err!("Division by zero."),
+        Power if ctx.signed && width.sign_extend(e2) < 0 =>
+          return err!(&format!("Can't raise a number to a negative power ({}).", width.sign_extend(e2))),
+        _ => (),
+      };
+      let result = match op {
         BitAnd      => e1 & e2,
         BitOr       => e1 | e2,
         BitXor      => e1 ^ e2,
-        BitShLeft   => e1 << e2,
+        BitShRight  if ctx.signed
+                    => width.wrap_signed(width.sign_extend(e1) >> e2),
         BitShRight  => e1 >> e2,
-        Plus        => e1 + e2,
-        Minus       => e1 - e2,
-        Times       => e1 * e2,
-        Divide      => e1 / e2,
+        BitRotLeft  => width.rotate_left(e1, e2),
+        BitRotRight => width.rotate_right(e1, e2),
+        BitShLeft | Plus | Minus | Times | Divide | Modulo | Power
+                    => arith(ctx, op, e1, e2)?,
+        Eq          => u128::from(e1 == e2),
+        NotEq       => u128::from(e1 != e2),
+        Less        => u128::from(compare(ctx, e1, e2) == Ordering::Less),
+        LessEq      => u128::from(compare(ctx, e1, e2) != Ordering::Greater),
+        Greater     => u128::from(compare(ctx, e1, e2) == Ordering::Greater),
+        GreaterEq   => u128::from(compare(ctx, e1, e2) != Ordering::Less),
+        LogAnd | LogOr
+                    => u128::from(e2 != 0),
       };
+      let result = width.truncate(result);
+      ctx.record(Step::Binary(op, e1, e2, result));
       Ok(result)
     },
     
-    UnaryOper(ref op, ref e) => {
+    UnaryOper(op, ref e) => {
       use ast::UnaryOp::*;
       let e = eval_expr(ctx, e)?;
-      let result = match *op {
+      let result = match op {
         BitNeg  => !e,
+        Negate  => e.wrapping_neg(),
+      };
+      let result = ctx.width.truncate(result);
+      ctx.record(Step::Unary(op, e, result));
+      Ok(result)
+    },
+    
+    Cond(ref cond, ref e1, ref e2) => {
+      let cond = eval_expr(ctx, cond)?;
+      let result = if cond != 0 {
+        eval_expr(ctx, e1)?
+      } else {
+        eval_expr(ctx, e2)?
       };
+      ctx.record(Step::Cond(cond, result));
       Ok(result)
     },
+    
+    Call(ref name, ref args) => {
+      if let Some(func) = ctx.funcs.get(name).cloned() {
+        return call(ctx, name, &func, args);
+      }
+      if let Some(conversion) = float::lookup(name) {
+        return convert(ctx, conversion, args);
+      }
+      let builtin = match builtins::lookup(name) {
+        Some(builtin) => builtin,
+        None => return err!(&format!("Function '{}' not found.", name)),
+      };
+      if args.len() != builtin.arity {
+        return err!(&format!("Function '{}' takes {} argument(s) but {} were given.",
+                             name, builtin.arity, args.len()));
+      }
+      let mut vals = Vec::with_capacity(args.len());
+      for arg in args {
+        vals.push(eval_expr(ctx, arg)?);
+      }
+      let result = ctx.width.truncate((builtin.func)(ctx.width, &vals));
+      ctx.record(Step::Call(builtin.name, vals, result));
+      Ok(result)
+    },
+  }
+}
+
+/// Call a conversion between numbers and the bits of floats. Floats are
+/// converted to whole numbers by rounding toward zero.
+fn convert(ctx: &mut Context, conversion: &Conversion, args: &[Expr]) -> EvalResult<u128> {
+  if args.len() != 1 {
+    return err!(&format!("Function '{}' takes 1 argument(s) but {} were given.",
+                         conversion.name, args.len()));
+  }
+  let format = conversion.format;
+  if format.bits() > ctx.width.bits() {
+    return err!(&format!("{} needs {} bits, but the word only has {}. Try ':width {}'.",
+                         conversion.name, format.bits(), ctx.width, format.bits()));
+  }
+  if conversion.to_bits {
+    return Ok(format.encode(eval_float(ctx, &args[0])?));
+  }
+  let value = format.decode(eval_expr(ctx, &args[0])?);
+  let whole = value.trunc();
+  let bits = ctx.width.bits() as i32;
+  if value.is_nan() || value.is_infinite() {
+    return err!(&format!("{} is {}, which isn't a whole number.",
+                         conversion.name, if value.is_nan() { "NaN" } else { "infinite" }));
+  }
+  if ctx.signed {
+    let limit = 2f64.powi(bits - 1);
+    if whole < -limit || whole >= limit {
+      return err!(&format!("{:?} doesn't fit in {} signed bits.", value, ctx.width));
+    }
+    Ok(ctx.width.wrap_signed(whole as i128))
+  } else if whole < 0.0 {
+    err!(&format!("{:?} is negative. Turn on signed mode with ':signed on' to get negative numbers.",
+                  value))
+  } else if whole >= 2f64.powi(bits) {
+    err!(&format!("{:?} doesn't fit in {} bits.", value, ctx.width))
+  } else {
+    Ok(whole as u128)
+  }
+}
+
+/// Evaluate the argument of a conversion to bits. This can be a float, the
+/// float from a conversion back from bits, or a whole number.
+fn eval_float(ctx: &mut Context, expr: &Expr) -> EvalResult<f64> {
+  use ast::ExprKind::*;
+  let val = match expr.kind {
+    Float(val) => Ok(val),
+    UnaryOper(UnaryOp::Negate, ref e) => eval_float(ctx, e).map(|val| -val),
+    Call(ref name, ref args) if args.len() == 1 && !ctx.funcs.contains_key(name) => {
+      match float::lookup(name) {
+        Some(conversion) if !conversion.to_bits
+            && conversion.format.bits() <= ctx.width.bits() => {
+          let x = eval_expr(ctx, &args[0])?;
+          Ok(conversion.format.decode(x))
+        },
+        _ => eval_whole(ctx, expr),
+      }
+    },
+    _ => eval_whole(ctx, expr),
+  };
+  val.map_err(|e| e.at(expr.span))
+}
+
+/// Evaluate an expression as a whole number, signed in signed mode.
+fn eval_whole(ctx: &mut Context, expr: &Expr) -> EvalResult<f64> {
+  let x = eval_expr(ctx, expr)?;
+  Ok(if ctx.signed { ctx.width.sign_extend(x) as f64 } else { x as f64 })
+}
+
+/// Call a function defined by the user. The arguments are evaluated in the
+/// caller's scope, and the body in a new scope holding the parameters. Errors
+/// in the body are reported as errors in the call, since the body wasn't
+/// parsed from the current input.
+fn call(ctx: &mut Context, name: &str, func: &Function, args: &[Expr]) -> EvalResult<u128> {
+  if args.len() != func.params.len() {
+    return err!(&format!("Function '{}' takes {} argument(s) but {} were given.",
+                         name, func.params.len(), args.len()));
+  }
+  if ctx.frames.iter().any(|frame| frame.name == name) {
+    return err!(&format!("Function '{}' calls itself, but recursion isn't allowed.", name));
+  }
+  let mut locals = HashMap::new();
+  for (param, arg) in func.params.iter().zip(args) {
+    let val = eval_expr(ctx, arg)?;
+    locals.insert(param.clone(), val);
+  }
+  ctx.frames.push(Frame { name: name.to_string(), locals });
+  let result = eval_expr(ctx, &func.body);
+  ctx.frames.pop();
+  result.map_err(|e| EvalError::new(&format!("In function '{}': {}", name, e.msg)))
+}
+
+/// Compare two words, as signed integers if the context is in signed mode.
+fn compare(ctx: &Context, e1: u128, e2: u128) -> Ordering {
+  if ctx.signed {
+    ctx.width.sign_extend(e1).cmp(&ctx.width.sign_extend(e2))
+  } else {
+    e1.cmp(&e2)
+  }
+}
+
+/// Perform an arithmetic operation which might overflow, and handle any
+/// overflow according to the context's policy. Shifting left counts as
+/// arithmetic, since it's the same as multiplying by a power of two.
+fn arith(ctx: &Context, op: BinOp, e1: u128, e2: u128) -> EvalResult<u128> {
+  use ast::BinOp::*;
+  let width = ctx.width;
+  
+  // Work out the result if it were computed without overflowing, the result
+  // when it wraps around, and the value to clamp to when saturating.
+  let (fits, wrapped, saturated) = if ctx.signed {
+    let (a, b) = (width.sign_extend(e1), width.sign_extend(e2));
+    let (exact, wrapped, positive) = match op {
+      Plus      => (a.checked_add(b), a.wrapping_add(b), b > 0),
+      Minus     => (a.checked_sub(b), a.wrapping_sub(b), b < 0),
+      Times     => (a.checked_mul(b), a.wrapping_mul(b), (a < 0) == (b < 0)),
+      Divide    => (a.checked_div(b), a.wrapping_div(b), true),
+      Modulo    => (Some(a.wrapping_rem(b)), a.wrapping_rem(b), true),
+      Power     => (checked_pow_signed(a, b), wrapping_pow(a as u128, b as u128) as i128,
+                    a >= 0 || b % 2 == 0),
+      BitShLeft => {
+        let shifted = a << b;
+        let exact = if shifted >> b == a { Some(shifted) } else { None };
+        (exact, shifted, a >= 0)
+      },
+      _ => unreachable!("{:?} is not an arithmetic operation", op),
+    };
+    let fits = exact.is_some_and(|x| width.fits_signed(x));
+    let saturated = if positive { width.signed_max() } else { width.signed_min() };
+    (fits, width.wrap_signed(wrapped), width.wrap_signed(saturated))
+  } else {
+    let (exact, wrapped, saturated) = match op {
+      Plus      => (e1.checked_add(e2), e1.wrapping_add(e2), width.mask()),
+      Minus     => (e1.checked_sub(e2), e1.wrapping_sub(e2), 0),
+      Times     => (e1.checked_mul(e2), e1.wrapping_mul(e2), width.mask()),
+      Divide    => (e1.checked_div(e2), e1 / e2, width.mask()),
+      Modulo    => (e1.checked_rem(e2), e1 % e2, width.mask()),
+      Power     => (checked_pow(e1, e2), wrapping_pow(e1, e2), width.mask()),
+      BitShLeft => {
+        let shifted = e1 << e2;
+        let exact = if shifted >> e2 == e1 { Some(shifted) } else { None };
+        (exact, shifted, width.mask())
+      },
+      _ => unreachable!("{:?} is not an arithmetic operation", op),
+    };
+    let fits = exact.is_some_and(|x| width.fits(x));
+    (fits, width.truncate(wrapped), saturated)
+  };
+  
+  if fits {
+    return Ok(wrapped);
+  }
+  match ctx.overflow {
+    Overflow::Wrapping => Ok(wrapped),
+    Overflow::Saturating => Ok(saturated),
+    Overflow::Checked => {
+      let (a, b) = if ctx.signed {
+        (format!("{}", width.sign_extend(e1)), format!("{}", width.sign_extend(e2)))
+      } else {
+        (format!("{}", e1), format!("{}", e2))
+      };
+      err!(&format!("Overflow: {} {:?} {} doesn't fit in {} bits.", a, op, b, width))
+    },
+  }
+}
+
+/// Evaluate the high and low bits of a slice, checking that they're in the
+/// word and that the high bit comes first. A slice of a single bit has no low
+/// bit, so it's the same as the high bit.
+fn eval_bounds(ctx: &mut Context, hi: &Expr, lo: Option<&Expr>) -> EvalResult<(u32, u32)> {
+  let high = eval_bit(ctx, hi)?;
+  let low = match lo {
+    Some(lo) => eval_bit(ctx, lo)?,
+    None => high,
+  };
+  if high < low {
+    let msg = format!("The high bit of a slice comes first, but {} is less than {}.", high, low);
+    return Err(EvalError::new(&msg).at(hi.span.to(lo.map_or(hi.span, |lo| lo.span))));
+  }
+  Ok((high, low))
+}
+
+/// Evaluate the number of a bit, checking that it's in the word.
+fn eval_bit(ctx: &mut Context, e: &Expr) -> EvalResult<u32> {
+  let bit = eval_expr(ctx, e)?;
+  let bits = ctx.width.bits();
+  if ctx.signed && ctx.width.sign_extend(bit) < 0 || bit >= u128::from(bits) {
+    let bit = if ctx.signed { ctx.width.sign_extend(bit) } else { bit as i128 };
+    let msg = format!("There's no bit {} in a {}-bit word. Bits go from 0 to {}.", bit, bits, bits - 1);
+    return Err(EvalError::new(&msg).at(e.span));
+  }
+  Ok(bit as u32)
+}
+
+/// Evaluate an expression whose width is known, returning its value and the
+/// number of bits it has. Slices, concatenations and replications have a
+/// width, as does a conditional whose chosen branch has one, but other
+/// expressions could use any number of bits of the word.
+fn eval_sized(ctx: &mut Context, expr: &Expr) -> EvalResult<(u128, u32)> {
+  use ast::ExprKind::*;
+  let sized = match expr.kind {
+    Slice(ref e, ref hi, ref lo) => {
+      let val = eval_expr(ctx, e)?;
+      let (hi, lo) = eval_bounds(ctx, hi, lo.as_deref())?;
+      Ok(((val >> lo) & slice_mask(hi - lo + 1), hi - lo + 1))
+    },
+    Concat(ref items) => eval_concat(ctx, items),
+    Replicate(ref count, ref items) => eval_replicate(ctx, count, items),
+    Field(ref e, ref name) => field_of(ctx, e, name).and_then(|field| {
+      eval_expr(ctx, e).map(|val| (field.extract(val), field.bits))
+    }),
+    Cond(ref cond, ref e1, ref e2) => {
+      let cond = eval_expr(ctx, cond)?;
+      let (val, bits) = if cond != 0 {
+        eval_sized(ctx, e1)?
+      } else {
+        eval_sized(ctx, e2)?
+      };
+      ctx.record(Step::Cond(cond, val));
+      Ok((val, bits))
+    },
+    _ => err!(&format!("Can't tell how many bits {} has. Take a slice of it, like {}[3:0].",
+                       expr, expr)),
+  };
+  sized.map_err(|e| e.at(expr.span))
+}
+
+/// Find the field with this name in the layout of a variable. The layout has
+/// to fit in the word.
+fn field_of(ctx: &Context, e: &Expr, name: &str) -> EvalResult<Field> {
+  let layout = match e.kind {
+    ExprKind::Var(ref var) => ctx.layout_of(var),
+    _ => None,
+  };
+  let layout = match layout {
+    Some(layout) => layout,
+    None => return err!(&format!("{} has no layout, so it has no field '{}'. \
+                                  Give a variable a layout like so: 'let v: LAYOUT = x'.", e, name)),
+  };
+  if let Err(msg) = layout.fits(ctx.width) {
+    return err!(&msg);
+  }
+  match layout.field(name) {
+    Some(field) => Ok(field.clone()),
+    None => err!(&format!("Layout {} has no field '{}'.", layout.name, name)),
+  }
+}
+
+/// Evaluate each expression in a concatenation and join their bits, with the
+/// first expression in the highest bits.
+fn eval_concat(ctx: &mut Context, items: &[Expr]) -> EvalResult<(u128, u32)> {
+  let (mut val, mut size) = (0, 0);
+  for item in items {
+    let (v, bits) = eval_sized(ctx, item)?;
+    size += bits;
+    if size > ctx.width.bits() {
+      return err!(&format!("Concatenating these takes at least {} bits, which don't fit in a {}-bit word.",
+                           size, ctx.width));
+    }
+    val = if bits == 128 { v } else { (val << bits) | v };
+  }
+  Ok((val, size))
+}
+
+/// Evaluate a replication, which joins count copies of the bits of the
+/// expressions.
+fn eval_replicate(ctx: &mut Context, count: &Expr, items: &[Expr]) -> EvalResult<(u128, u32)> {
+  let n = eval_expr(ctx, count)?;
+  if n == 0 {
+    return Err(EvalError::new("Can't repeat something 0 times.").at(count.span));
+  }
+  let (v, bits) = eval_concat(ctx, items)?;
+  let size = n.saturating_mul(u128::from(bits));
+  if size > u128::from(ctx.width.bits()) {
+    return err!(&format!("Repeating {} bits {} times takes {} bits, which don't fit in a {}-bit word.",
+                         bits, n, size, ctx.width));
+  }
+  let mut val = 0;
+  for _ in 0..n {
+    val = if bits == 128 { v } else { (val << bits) | v };
+  }
+  Ok((val, size as u32))
+}
+
+/// A mask of the lowest size bits.
+fn slice_mask(size: u32) -> u128 {
+  if size >= 128 { u128::MAX } else { (1 << size) - 1 }
+}
+
+/// Raise base to the power exp, or return nothing if the result doesn't fit
+/// in 128 bits.
+fn checked_pow(base: u128, exp: u128) -> Option<u128> {
+  match base {
+    0 | 1 => Some(if exp == 0 { 1 } else { base }),
+    _ if exp >= 128 => None,
+    _ => base.checked_pow(exp as u32),
+  }
+}
+
+/// Raise a signed base to a non-negative power exp, or return nothing if the
+/// result doesn't fit in 128 bits.
+fn checked_pow_signed(base: i128, exp: i128) -> Option<i128> {
+  match base {
+    0 | 1 => Some(if exp == 0 { 1 } else { base }),
+    -1 => Some(if exp % 2 == 0 { 1 } else { -1 }),
+    _ if exp >= 128 => None,
+    _ => base.checked_pow(exp as u32),
   }
 }
+
+/// Raise base to the power exp, wrapping around when the result doesn't fit
+/// in 128 bits.
+fn wrapping_pow(mut base: u128, mut exp: u128) -> u128 {
+  let mut result: u128 = 1;
+  while exp > 0 {
+    if exp & 1 == 1 {
+      result = result.wrapping_mul(base);
+    }
+    base = base.wrapping_mul(base);
+    exp >>= 1;
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use lexer::lex;
+  use parser::parse;
+  use width::WIDTHS;
+
+  /// A context with variables max and min holding the largest and smallest
+  /// values of a word, and m1 holding -1 in signed mode.
+  fn context(bits: u32, signed: bool, overflow: Overflow) -> Context {
+    let width = Width::new(bits).unwrap();
+    let mut ctx = Context::new(width, signed);
+    ctx.set_overflow(overflow);
+    if signed {
+      ctx.insert("max", width.wrap_signed(width.signed_max()));
+      ctx.insert("min", width.wrap_signed(width.signed_min()));
+      ctx.insert("m1", width.wrap_signed(-1));
+    } else {
+      ctx.insert("max", width.mask());
+      ctx.insert("min", 0);
+    }
+    ctx
+  }
+
+  fn run(ctx: &mut Context, input: &str) -> EvalResult<u128> {
+    let prog = parse(&lex(input).unwrap()).unwrap();
+    eval(ctx, &prog).map(Option::unwrap)
+  }
+
+  #[test]
+  fn division_by_zero() {
+    for &overflow in &[Overflow::Wrapping, Overflow::Checked, Overflow::Saturating] {
+      for &signed in &[false, true] {
+        let mut ctx = context(16, signed, overflow);
+        assert!(run(&mut ctx, "1 / 0").is_err());
+        assert!(run(&mut ctx, "1 % 0").is_err());
+      }
+    }
+  }
+
+  #[test]
+  fn oversized_shifts() {
+    let mut ctx = context(16, false, Overflow::Wrapping);
+    assert!(run(&mut ctx, "1 << 16").is_err());
+    assert!(run(&mut ctx, "1 >> 16").is_err());
+    assert_eq!(run(&mut ctx, "1 << 15").unwrap(), 0x8000);
+    let e = run(&mut ctx, "2 + (1 << 16)").unwrap_err();
+    assert_eq!(e.span(), Some(Span::new(4, 13)));
+  }
+
+  #[test]
+  fn unsigned_overflow() {
+    for &bits in WIDTHS.iter() {
+      let mask = Width::new(bits).unwrap().mask();
+      let mut ctx = context(bits, false, Overflow::Wrapping);
+      assert_eq!(run(&mut ctx, "max + 1").unwrap(), 0);
+      assert_eq!(run(&mut ctx, "0 - 1").unwrap(), mask);
+      assert_eq!(run(&mut ctx, "max * 2").unwrap(), mask - 1);
+      assert_eq!(run(&mut ctx, "max << 1").unwrap(), mask - 1);
+      
+      let mut ctx = context(bits, false, Overflow::Checked);
+      assert!(run(&mut ctx, "max + 1").is_err());
+      assert!(run(&mut ctx, "0 - 1").is_err());
+      assert!(run(&mut ctx, "max * 2").is_err());
+      assert!(run(&mut ctx, "max << 1").is_err());
+      assert!(run(&mut ctx, "2 ** 128").is_err());
+      assert_eq!(run(&mut ctx, "max - 1").unwrap(), mask - 1);
+      
+      let mut ctx = context(bits, false, Overflow::Saturating);
+      assert_eq!(run(&mut ctx, "max + 1").unwrap(), mask);
+      assert_eq!(run(&mut ctx, "0 - 1").unwrap(), 0);
+      assert_eq!(run(&mut ctx, "max * 2").unwrap(), mask);
+      assert_eq!(run(&mut ctx, "3 ** 200").unwrap(), mask);
+    }
+  }
+
+  #[test]
+  fn signed_overflow() {
+    for &bits in WIDTHS.iter() {
+      let width = Width::new(bits).unwrap();
+      let (max, min) = (width.wrap_signed(width.signed_max()), width.wrap_signed(width.signed_min()));
+      
+      let mut ctx = context(bits, true, Overflow::Wrapping);
+      assert_eq!(run(&mut ctx, "max + 1").unwrap(), min);
+      assert_eq!(run(&mut ctx, "min - 1").unwrap(), max);
+      assert_eq!(run(&mut ctx, "min / m1").unwrap(), min);
+      assert_eq!(run(&mut ctx, "m1 >> 1").unwrap(), width.wrap_signed(-1));
+      assert_eq!(run(&mut ctx, "-7 / 2").unwrap(), width.wrap_signed(-3));
+      assert_eq!(run(&mut ctx, "-1 >> 1").unwrap(), width.wrap_signed(-1));
+      
+      let mut ctx = context(bits, true, Overflow::Checked);
+      assert!(run(&mut ctx, "max + 1").is_err());
+      assert!(run(&mut ctx, "min - 1").is_err());
+      assert!(run(&mut ctx, "min / m1").is_err());
+      assert!(run(&mut ctx, "max * 2").is_err());
+      assert!(run(&mut ctx, &format!("1 << {}", bits - 1)).is_err());
+      assert!(run(&mut ctx, "2 ** -1").is_err());
+      assert_eq!(run(&mut ctx, "min + 1 - 1").unwrap(), min);
+      
+      let mut ctx = context(bits, true, Overflow::Saturating);
+      assert_eq!(run(&mut ctx, "max + 1").unwrap(), max);
+      assert_eq!(run(&mut ctx, "min - 1").unwrap(), min);
+      assert_eq!(run(&mut ctx, "min * 2").unwrap(), min);
+      assert_eq!(run(&mut ctx, "min * m1").unwrap(), max);
+      assert_eq!(run(&mut ctx, "min / m1").unwrap(), max);
+      assert_eq!(run(&mut ctx, "min * -1").unwrap(), max);
+      assert_eq!(run(&mut ctx, "(-2) ** 127").unwrap(), min);
+    }
+  }
+
+}
@@ -0,0 +1,289 @@
+
+use std::fmt;
+
+/// An IEEE-754 binary floating point format, made of a sign bit, then the
+/// exponent bits, then the mantissa bits.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FloatFormat {
+  pub name: &'static str,
+  pub exp_bits: u32,
+  pub man_bits: u32,
+}
+
+pub const F16: FloatFormat = FloatFormat { name: "f16", exp_bits: 5, man_bits: 10 };
+pub const BF16: FloatFormat = FloatFormat { name: "bf16", exp_bits: 8, man_bits: 7 };
+pub const F32: FloatFormat = FloatFormat { name: "f32", exp_bits: 8, man_bits: 23 };
+pub const F64: FloatFormat = FloatFormat { name: "f64", exp_bits: 11, man_bits: 52 };
+
+/// Every float format, in the order they are listed to the user.
+pub static FORMATS: [FloatFormat; 4] = [F16, BF16, F32, F64];
+
+/// What kind of number a float is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Class {
+  Zero,
+  Subnormal,
+  Normal,
+  Infinite,
+  Nan,
+}
+
+/// A float taken apart into its fields.
+#[derive(Clone, Copy, Debug)]
+pub struct Parts {
+  pub sign: u128,
+  pub exp: u128,
+  pub man: u128,
+}
+
+impl FloatFormat {
+
+  /// Find the float format with this name, e.g. "f32".
+  pub fn parse(s: &str) -> Option<FloatFormat> {
+    FORMATS.iter().find(|format| format.name == s.trim()).cloned()
+  }
+
+  /// The number of bits in a float.
+  pub fn bits(&self) -> u32 {
+    1 + self.exp_bits + self.man_bits
+  }
+
+  /// The number taken away from the exponent field to get the exponent.
+  fn bias(&self) -> i32 {
+    (1 << (self.exp_bits - 1)) - 1
+  }
+
+  /// Take the lowest bits of a word apart into the fields of a float.
+  pub fn parts(&self, x: u128) -> Parts {
+    Parts {
+      sign: (x >> (self.exp_bits + self.man_bits)) & 1,
+      exp: (x >> self.man_bits) & ((1 << self.exp_bits) - 1),
+      man: x & ((1 << self.man_bits) - 1),
+    }
+  }
+
+  /// Work out what kind of number the float with these bits is.
+  pub fn classify(&self, x: u128) -> Class {
+    let parts = self.parts(x);
+    let max_exp = (1 << self.exp_bits) - 1;
+    match (parts.exp, parts.man) {
+      (0, 0) => Class::Zero,
+      (0, _) => Class::Subnormal,
+      (exp, 0) if exp == max_exp => Class::Infinite,
+      (exp, _) if exp == max_exp => Class::Nan,
+      _ => Class::Normal,
+    }
+  }
+
+  /// The value of the float with these bits. Every float in these formats
+  /// can be held exactly by an f64.
+  pub fn decode(&self, x: u128) -> f64 {
+    if *self == F64 {
+      return f64::from_bits(x as u64);
+    }
+    let parts = self.parts(x);
+    let magnitude = match self.classify(x) {
+      Class::Zero => 0.0,
+      Class::Infinite => f64::INFINITY,
+      Class::Nan => f64::NAN,
+      Class::Subnormal => parts.man as f64 * 2f64.powi(1 - self.bias() - self.man_bits as i32),
+      Class::Normal => {
+        let significand = (parts.man | (1 << self.man_bits)) as f64;
+        significand * 2f64.powi(parts.exp as i32 - self.bias() - self.man_bits as i32)
+      },
+    };
+    if parts.sign == 1 { -magnitude } else { magnitude }
+  }
+
+  /// The bits of the float nearest to a number, rounding halfway cases to
+  /// an even mantissa.
+  pub fn encode(&self, x: f64) -> u128 {
+    if *self == F64 {
+      return u128::from(x.to_bits());
+    }
+    let sign = u128::from(x.is_sign_negative()) << (self.exp_bits + self.man_bits);
+    let max_exp: u128 = (1 << self.exp_bits) - 1;
+    if x.is_nan() {
+      return (max_exp << self.man_bits) | (1 << (self.man_bits - 1));
+    }
+    if x.is_infinite() {
+      return sign | (max_exp << self.man_bits);
+    }
+    if x == 0.0 {
+      return sign;
+    }
+
+    // The number is m * 2^e for a whole number m.
+    let bits = x.to_bits();
+    let exp = ((bits >> 52) & 0x7ff) as i32;
+    let frac = u128::from(bits & ((1 << 52) - 1));
+    let (m, e) = if exp == 0 { (frac, -1074) } else { (frac | (1 << 52), exp - 1075) };
+
+    // Find the exponent of the highest bit, and shift the rest of the bits
+    // into the mantissa, or into a subnormal mantissa if the number is too
+    // small to be normal.
+    let top = e + (127 - m.leading_zeros() as i32);
+    let min_exp = 1 - self.bias();
+    let shift = if top >= min_exp {
+      top - e - self.man_bits as i32
+    } else {
+      (min_exp - self.man_bits as i32) - e
+    };
+    let mut sig = round_shift(m, shift);
+    let mut biased = if top >= min_exp { top + self.bias() } else { 0 };
+
+    // Rounding up can carry into the next power of two.
+    if sig >> (self.man_bits + 1) != 0 {
+      sig >>= 1;
+      biased += 1;
+    }
+    if biased >= max_exp as i32 {
+      return sign | (max_exp << self.man_bits);
+    }
+    if biased == 0 {
+      // A subnormal that rounded up to the smallest normal carries into the
+      // exponent by itself.
+      return sign | sig;
+    }
+    sign | ((biased as u128) << self.man_bits) | (sig & ((1 << self.man_bits) - 1))
+  }
+
+  /// Describe the float with these bits, e.g. "1.5 = 1.5 × 2^0" or
+  /// "quiet NaN".
+  pub fn describe(&self, x: u128) -> String {
+    let parts = self.parts(x);
+    let sign = if parts.sign == 1 { "-" } else { "+" };
+    let value = self.decode(x);
+    let shown = if *self == F32 { format!("{:?}", value as f32) } else { format!("{:?}", value) };
+    match self.classify(x) {
+      Class::Zero => format!("{}0", sign),
+      Class::Infinite => format!("{}inf", sign),
+      Class::Nan if parts.man >> (self.man_bits - 1) == 1 => "quiet NaN".to_string(),
+      Class::Nan => "signalling NaN".to_string(),
+      Class::Subnormal => format!("subnormal {}", shown),
+      Class::Normal => {
+        let exp = parts.exp as i32 - self.bias();
+        format!("{} = {:?} × 2^{}", shown, value / 2f64.powi(exp), exp)
+      },
+    }
+  }
+
+}
+
+impl fmt::Display for FloatFormat {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name)
+  }
+}
+
+/// Shift a number right, rounding to the nearest whole number and halfway
+/// cases to an even number.
+fn round_shift(m: u128, shift: i32) -> u128 {
+  if shift <= 0 {
+    return m << -shift;
+  }
+  if shift >= 128 {
+    return 0;
+  }
+  let kept = m >> shift;
+  let rest = m & ((1 << shift) - 1);
+  let half = 1 << (shift - 1);
+  if rest > half || (rest == half && kept & 1 == 1) { kept + 1 } else { kept }
+}
+
+/// A function converting between numbers and the bits of floats.
+pub struct Conversion {
+  pub name: &'static str,
+  pub format: FloatFormat,
+  /// Whether it turns a number into bits, rather than bits into a number.
+  pub to_bits: bool,
+  pub doc: &'static str,
+}
+
+/// Every conversion function, in the order they are listed to the user.
+pub static CONVERSIONS: [Conversion; 8] = [
+  Conversion { name: "f16bits", format: F16, to_bits: true,
+               doc: "f16bits(1.5): the bits of the nearest half precision float." },
+  Conversion { name: "bf16bits", format: BF16, to_bits: true,
+               doc: "bf16bits(1.5): the bits of the nearest bfloat16." },
+  Conversion { name: "f32bits", format: F32, to_bits: true,
+               doc: "f32bits(1.5): the bits of the nearest single precision float." },
+  Conversion { name: "f64bits", format: F64, to_bits: true,
+               doc: "f64bits(1.5): the bits of the nearest double precision float." },
+  Conversion { name: "bitsf16", format: F16, to_bits: false,
+               doc: "bitsf16(x): the half precision float with bits x, rounded toward zero." },
+  Conversion { name: "bitsbf16", format: BF16, to_bits: false,
+               doc: "bitsbf16(x): the bfloat16 with bits x, rounded toward zero." },
+  Conversion { name: "bitsf32", format: F32, to_bits: false,
+               doc: "bitsf32(x): the single precision float with bits x, rounded toward zero." },
+  Conversion { name: "bitsf64", format: F64, to_bits: false,
+               doc: "bitsf64(x): the double precision float with bits x, rounded toward zero." },
+];
+
+/// Find the conversion function with this name.
+pub fn lookup(name: &str) -> Option<&'static Conversion> {
+  CONVERSIONS.iter().find(|conversion| conversion.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn f16_limits() {
+    assert_eq!(F16.encode(65504.0), 0x7bff);
+    assert_eq!(F16.encode(65519.0), 0x7bff);
+    assert_eq!(F16.encode(65520.0), 0x7c00);
+    assert_eq!(F16.encode(-65520.0), 0xfc00);
+    assert_eq!(F16.decode(0x7bff), 65504.0);
+    assert_eq!(F16.classify(0x7c00), Class::Infinite);
+  }
+
+  #[test]
+  fn round_half_even() {
+    let ulp = 2f64.powi(-10);
+    assert_eq!(F16.encode(1.0 + ulp / 2.0), 0x3c00);
+    assert_eq!(F16.encode(1.0 + ulp * 1.5), 0x3c02);
+    assert_eq!(F16.encode(1.0 + ulp / 2.0 + ulp / 64.0), 0x3c01);
+    assert_eq!(F16.encode(2.0 - ulp / 2.0), 0x4000);
+    assert_eq!(BF16.encode(1.0 + 2f64.powi(-8)), 0x3f80);
+    assert_eq!(BF16.encode(1.0 + 3.0 * 2f64.powi(-8)), 0x3f82);
+  }
+
+  #[test]
+  fn subnormals() {
+    let tiny = 2f64.powi(-24);
+    assert_eq!(F16.encode(tiny), 0x0001);
+    assert_eq!(F16.encode(tiny / 2.0), 0x0000);
+    assert_eq!(F16.encode(tiny * 1.5), 0x0002);
+    assert_eq!(F16.encode(-tiny / 4.0), 0x8000);
+    assert_eq!(F16.encode(2f64.powi(-14) - tiny / 2.0), 0x0400);
+    assert_eq!(F16.classify(0x03ff), Class::Subnormal);
+    assert_eq!(F16.decode(0x0001), tiny);
+  }
+
+  #[test]
+  fn agrees_with_f32() {
+    let values = [0.1, -2.75, 1.0e-40, 3.4028235e38, 3.5e38, 1.0e-46, 0.5e-45, 0.75e-45, 16777217.0];
+    for &x in values.iter() {
+      assert_eq!(F32.encode(x), u128::from((x as f32).to_bits()), "encoding {}", x);
+    }
+    for &bits in [0x3dcccccd_u32, 0x80000001, 0x7f7fffff, 0x00800000].iter() {
+      assert_eq!(F32.decode(u128::from(bits)), f64::from(f32::from_bits(bits)));
+    }
+  }
+
+  #[test]
+  fn special_values() {
+    assert_eq!(F32.encode(f64::NAN), 0x7fc00000);
+    assert_eq!(F32.encode(f64::NEG_INFINITY), 0xff800000);
+    assert_eq!(F32.encode(-0.0), 0x80000000);
+    assert_eq!(F32.describe(0x7fc00000), "quiet NaN");
+    assert_eq!(F32.describe(0x7f800001), "signalling NaN");
+    assert_eq!(F32.describe(0xff800000), "-inf");
+    assert_eq!(F32.describe(0x80000000), "-0");
+    assert_eq!(F32.describe(0x3fc00000), "1.5 = 1.5 × 2^0");
+    assert_eq!(F16.describe(0x0001), "subnormal 5.960464477539063e-8");
+  }
+
+}
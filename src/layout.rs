@@ -0,0 +1,89 @@
+
+use std::fmt;
+use width::Width;
+
+/// A named field of a layout, made of the bits from lo up to hi.
+#[derive(Clone, Debug)]
+pub struct Field {
+  pub name: String,
+  pub lo: u32,
+  pub bits: u32,
+}
+
+impl Field {
+
+  /// The highest bit of the field.
+  pub fn hi(&self) -> u32 {
+    self.lo + self.bits - 1
+  }
+
+  /// The value of this field in a word.
+  pub fn extract(&self, x: u128) -> u128 {
+    let mask = if self.bits >= 128 { u128::MAX } else { (1 << self.bits) - 1 };
+    (x >> self.lo) & mask
+  }
+
+}
+
+/// A layout splits a word into named fields, like a C struct of bit fields.
+/// The first field has the lowest bits.
+#[derive(Clone, Debug)]
+pub struct Layout {
+  pub name: String,
+  pub fields: Vec<Field>,
+}
+
+impl Layout {
+
+  /// Lay out fields with these names and numbers of bits, from the lowest
+  /// bits up. Every field needs at least one bit and a name of its own, and
+  /// there can't be more than 128 bits altogether.
+  pub fn new(name: &str, fields: &[(String, u128)]) -> Result<Layout, String> {
+    let mut layout = Layout { name: name.to_string(), fields: Vec::new() };
+    let mut lo: u128 = 0;
+    for &(ref field, bits) in fields {
+      if layout.field(field).is_some() {
+        return Err(format!("Layout {} has more than one field called '{}'.", name, field));
+      }
+      if bits == 0 {
+        return Err(format!("Field '{}' of layout {} needs at least one bit.", field, name));
+      }
+      if lo + bits > 128 {
+        return Err(format!("Layout {} has more than 128 bits.", name));
+      }
+      layout.fields.push(Field { name: field.to_string(), lo: lo as u32, bits: bits as u32 });
+      lo += bits;
+    }
+    Ok(layout)
+  }
+
+  /// The number of bits in all the fields.
+  pub fn bits(&self) -> u32 {
+    self.fields.iter().map(|field| field.bits).sum()
+  }
+
+  /// Check that every field is in a word of this width.
+  pub fn fits(&self, width: Width) -> Result<(), String> {
+    if self.bits() > width.bits() {
+      return Err(format!("Layout {} has {} bits, which don't fit in a {}-bit word.",
+                         self.name, self.bits(), width));
+    }
+    Ok(())
+  }
+
+  /// Find the field with this name.
+  pub fn field(&self, name: &str) -> Option<&Field> {
+    self.fields.iter().find(|field| field.name == name)
+  }
+
+}
+
+/// Layouts are written the way they're defined.
+impl fmt::Display for Layout {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let fields: Vec<_> = self.fields.iter()
+                                    .map(|field| format!("{}:{}", field.name, field.bits))
+                                    .collect();
+    write!(f, "layout {} {{ {} }}", self.name, fields.join(", "))
+  }
+}
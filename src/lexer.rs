@@ -1,4 +1,5 @@
 
+use span::Span;
 use std::error::Error;
 use std::fmt;
 use std::iter::Peekable;
@@ -8,14 +9,21 @@ use std::str::Chars;
 #[derive(Debug)]
 pub struct LexError {
   msg: String,
+  span: Span,
 }
 
 impl LexError {
-  fn new(msg:&str) -> LexError {
+  fn new(span: Span, msg:&str) -> LexError {
     LexError {
       msg: msg.to_string(),
+      span,
     }
   }
+  
+  /// The part of the input where the error happened.
+  pub fn span(&self) -> Span {
+    self.span
+  }
 }
 
 impl fmt::Display for LexError {
@@ -34,7 +42,7 @@ type LexResult<T> = Result<T, LexError>;
 
 /// Short-hand for generating lexing errors.
 macro_rules! err {
-  ($msg:expr) => (Err(LexError::new($msg)));
+  ($span:expr, $msg:expr) => (Err(LexError::new($span, $msg)));
 }
 
 
@@ -43,46 +51,137 @@ macro_rules! err {
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Token {
-  Ident(String), Num(u16), Oper(Operator), LeftParen, RightParen, Keyw(Keyword), Equals
+  Ident(String), Num(u128), Oper(Operator), LeftParen, RightParen, Comma, Keyw(Keyword), Equals,
+  Question, Colon, Result(Option<usize>), LeftBracket, RightBracket,
+  LeftBrace, RightBrace, Dot, Float(String)
 }
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Keyword {
-  Let,
+  Let, Fn, Layout,
 }
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Operator {
-  Plus, Minus, Times, Divide,
+  Plus, Minus, Times, Divide, Modulo, Power,
   BitNeg, BitAnd, BitOr, BitXor,
-  BitShLeft, BitShRight
+  BitShLeft, BitShRight, BitRotLeft, BitRotRight,
+  Eq, NotEq, Less, LessEq, Greater, GreaterEq,
+  LogAnd, LogOr
 }
 
-pub fn lex(input: &str) -> LexResult<Vec<Token>> {
+impl fmt::Display for Token {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use self::Token::*;
+    match *self {
+      Ident(ref name) => write!(f, "'{}'", name),
+      Num(val)        => write!(f, "'{}'", val),
+      Oper(ref op)    => write!(f, "'{}'", op),
+      LeftParen       => write!(f, "'('"),
+      RightParen      => write!(f, "')'"),
+      Comma           => write!(f, "','"),
+      Keyw(ref kw)    => write!(f, "'{}'", kw),
+      Equals          => write!(f, "'='"),
+      Question        => write!(f, "'?'"),
+      Colon           => write!(f, "':'"),
+      Result(None)    => write!(f, "'_'"),
+      Result(Some(n)) => write!(f, "'${}'", n),
+      LeftBracket     => write!(f, "'['"),
+      RightBracket    => write!(f, "']'"),
+      LeftBrace       => write!(f, "'{{'"),
+      RightBrace      => write!(f, "'}}'"),
+      Dot             => write!(f, "'.'"),
+      Float(ref val)  => write!(f, "'{}'", val),
+    }
+  }
+}
+
+impl fmt::Display for Keyword {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", match *self {
+      Keyword::Let => "let",
+      Keyword::Fn  => "fn",
+      Keyword::Layout => "layout",
+    })
+  }
+}
+
+impl fmt::Display for Operator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use self::Operator::*;
+    write!(f, "{}", match *self {
+      Plus        => "+",
+      Minus       => "-",
+      Times       => "*",
+      Divide      => "/",
+      Modulo      => "%",
+      Power       => "**",
+      BitNeg      => "!",
+      BitAnd      => "&",
+      BitOr       => "|",
+      BitXor      => "^",
+      BitShLeft   => "<<",
+      BitShRight  => ">>",
+      BitRotLeft  => "<<<",
+      BitRotRight => ">>>",
+      Eq          => "==",
+      NotEq       => "!=",
+      Less        => "<",
+      LessEq      => "<=",
+      Greater     => ">",
+      GreaterEq   => ">=",
+      LogAnd      => "&&",
+      LogOr       => "||",
+    })
+  }
+}
+
+/// Split the input into tokens, along with the span of the input each token
+/// came from.
+pub fn lex(input: &str) -> LexResult<Vec<(Token, Span)>> {
   let mut lexer = Lexer {
     input: input.chars().peekable(),
     tokens: Vec::new(),
+    pos: 0,
+    start: 0,
   };
+  lexer.skip_whitespace()?;
   while !lexer.done() {
-    lexer.skip_whitespace()?;
     lexer.lex_token()?;
+    lexer.skip_whitespace()?;
   }
   Ok(lexer.tokens)
 }
 
 struct Lexer<'l> {
   input: Peekable<Chars<'l>>,
-  tokens: Vec<Token>,
+  tokens: Vec<(Token, Span)>,
+  /// The byte offset of the next character.
+  pos: usize,
+  /// The byte offset where the current token starts.
+  start: usize,
 }
 
 fn is_symbol(c: char) -> bool {
-  let symbols = vec!['+', '*', '/', '-', '&', '|', '^', '!', '<', '>'];
+  let symbols = vec!['+', '*', '/', '%', '-', '&', '|', '^', '!', '<', '>', '='];
   symbols.contains(&c)
 }
 
+/// The name of a base that numbers can be written in.
+fn base_name(radix: u32) -> &'static str {
+  match radix {
+    2 => "binary",
+    8 => "octal",
+    16 => "hexadecimal",
+    _ => "decimal",
+  }
+}
+
 fn as_keyword(s: &str) -> Option<Keyword> {
   match s {
     "let" => Some(Keyword::Let),
+    "fn" => Some(Keyword::Fn),
+    "layout" => Some(Keyword::Layout),
     _ => None,
   }
 }
@@ -94,11 +193,32 @@ impl<'l> Lexer<'l> {
   
   fn next(&mut self) -> LexResult<char> {
     match self.input.next() {
-      Some(ch) => Ok(ch),
-      None     => err!("Expected character but there wasn't one."),
+      Some(ch) => {
+        self.pos += ch.len_utf8();
+        Ok(ch)
+      },
+      None     => err!(self.here(), "Expected character but there wasn't one."),
     }
   }
   
+  /// The span of the current token, up to the next character.
+  fn span(&self) -> Span {
+    Span::new(self.start, self.pos)
+  }
+  
+  /// The span of the next character.
+  fn here(&mut self) -> Span {
+    let len = self.peek().map_or(0, |ch| ch.len_utf8());
+    Span::new(self.pos, self.pos + len)
+  }
+  
+  /// Add a token which spans from the start of the current token up to the
+  /// next character.
+  fn push(&mut self, token: Token) {
+    let span = self.span();
+    self.tokens.push((token, span));
+  }
+  
   fn skip_whitespace(&mut self) -> LexResult<()> {
     while let Some(&ch) = self.peek() {
       if ch.is_whitespace() {
@@ -115,25 +235,49 @@ impl<'l> Lexer<'l> {
   }
   
   fn lex_token(&mut self) -> LexResult<()> {
-    if self.done() { return err!("No characters left while lexing token.") };
+    self.start = self.pos;
+    if self.done() { return err!(self.here(), "No characters left while lexing token.") };
     let ch = *self.peek().unwrap();
     if ch.is_numeric() {
       self.lex_num()?;
     } else if ch.is_alphabetic() {
-      let name = self.lex_ident()?;
+      self.lex_ident()?;
     } else if is_symbol(ch) {
       self.lex_operator()?;
     } else if ch == '(' {
-      self.tokens.push(Token::LeftParen);
       self.next()?;
+      self.push(Token::LeftParen);
     } else if ch == ')' {
-      self.tokens.push(Token::RightParen);
       self.next()?;
-    } else if ch == '=' {
-      self.tokens.push(Token::Equals);
-      self.next();
+      self.push(Token::RightParen);
+    } else if ch == '[' {
+      self.next()?;
+      self.push(Token::LeftBracket);
+    } else if ch == ']' {
+      self.next()?;
+      self.push(Token::RightBracket);
+    } else if ch == '{' {
+      self.next()?;
+      self.push(Token::LeftBrace);
+    } else if ch == '}' {
+      self.next()?;
+      self.push(Token::RightBrace);
+    } else if ch == '.' {
+      self.next()?;
+      self.push(Token::Dot);
+    } else if ch == ',' {
+      self.next()?;
+      self.push(Token::Comma);
+    } else if ch == '?' {
+      self.next()?;
+      self.push(Token::Question);
+    } else if ch == ':' {
+      self.next()?;
+      self.push(Token::Colon);
+    } else if ch == '_' || ch == '$' {
+      self.lex_result()?;
     } else {
-      return err!(&format!("Couldn't lex token. Failed on character {}", ch));
+      return err!(self.here(), &format!("Couldn't lex token. Failed on character {}", ch));
     };
     Ok(())
   }
@@ -143,46 +287,118 @@ impl<'l> Lexer<'l> {
     // Must have at least one digit in number.
     let ch = self.next()?;
     
-    if !ch.is_numeric() {
-      return err!("Non-digit found while lexing number.");
+    if !ch.is_ascii_digit() {
+      return err!(self.span(), "Non-digit found while lexing number.");
+    }
+    
+    // A leading zero may be followed by a prefix giving the base.
+    let mut radix = 10;
+    if ch == '0' {
+      radix = match self.peek() {
+        Some(&'x') | Some(&'X') => 16,
+        Some(&'o') | Some(&'O') => 8,
+        Some(&'b') | Some(&'B') => 2,
+        _ => 10,
+      };
     }
     let mut num = String::new();
-    num.push(ch);
+    if radix == 10 {
+      num.push(ch);
+    } else {
+      self.next()?;
+    }
   
-    // Keep adding digits to the number.
+    // Keep adding digits to the number. Underscores may be used to separate
+    // groups of digits, e.g. 0b1111_0000.
     while let Some(&ch) = self.peek() {
-      if ch.is_numeric() {
+      if ch == '_' {
+        self.next()?;
+      } else if ch.is_digit(radix) {
         num.push(ch);
         self.next()?;
-      } else if ch.is_alphabetic() {
-        return err!(&format!("Expected digit while parsing number but found '{}'", ch));
+      } else if radix == 10 && self.float_follows() {
+        return self.lex_float(num);
+      } else if ch.is_alphanumeric() {
+        return err!(self.here(), &format!("Invalid digit '{}' in {} number.", ch, base_name(radix)));
       } else {
         break;
       }
     }
+    if num.is_empty() {
+      return err!(self.span(),
+                  &format!("Expected {} digits after prefix but found none.", base_name(radix)));
+    }
   
-    // Parse as u16.
-    match num.parse::<u16>() {
-      Ok(val) => self.tokens.push(Token::Num(val)),
-      Err(e)  => return err!(&format!("Failed to parse {} as u16: {}", num, e)),
+    // Parse as u128. Whether it fits in a word is checked during evaluation,
+    // since the word width can change between lexing and evaluating.
+    match u128::from_str_radix(&num, radix) {
+      Ok(val) => self.push(Token::Num(val)),
+      Err(e)  => return err!(self.span(), &format!("Failed to parse {} as u128: {}", num, e)),
     }
     Ok(())
   
   }
   
+  /// Whether the next characters carry on a decimal number as a float: a
+  /// '.' followed by a digit, or an exponent like e-3.
+  fn float_follows(&self) -> bool {
+    let mut rest = self.input.clone();
+    match rest.next() {
+      Some('.') => rest.next().is_some_and(|ch| ch.is_ascii_digit()),
+      Some('e') | Some('E') => match rest.next() {
+        Some('+') | Some('-') => rest.next().is_some_and(|ch| ch.is_ascii_digit()),
+        Some(ch) => ch.is_ascii_digit(),
+        None => false,
+      },
+      _ => false,
+    }
+  }
+  
+  /// Lex the rest of a float, such as 1.5 or 6.02e23, whose whole part has
+  /// already been lexed.
+  fn lex_float(&mut self, mut num: String) -> LexResult<()> {
+    let mut exponent = false;
+    while let Some(&ch) = self.peek() {
+      if ch == '_' {
+        self.next()?;
+      } else if ch.is_ascii_digit() || (ch == '.' && !exponent && !num.contains('.')) {
+        num.push(ch);
+        self.next()?;
+      } else if (ch == 'e' || ch == 'E') && !exponent {
+        exponent = true;
+        num.push(self.next()?);
+        if let Some(&sign) = self.peek() {
+          if sign == '+' || sign == '-' {
+            num.push(self.next()?);
+          }
+        }
+      } else if ch.is_alphanumeric() || ch == '.' {
+        return err!(self.here(), &format!("Invalid character '{}' in float.", ch));
+      } else {
+        break;
+      }
+    }
+    match num.parse::<f64>() {
+      Ok(_) => self.push(Token::Float(num)),
+      Err(e) => return err!(self.span(), &format!("Failed to parse {} as a float: {}", num, e)),
+    }
+    Ok(())
+  }
+  
   fn lex_ident(&mut self) -> LexResult<()> {
   
     // An identifier must start with an alphabetic character.
     let ch = self.next()?;
     if !ch.is_alphabetic() {
-      return err!("An identifier must start with an alphabetic character.");
+      return err!(self.span(), "An identifier must start with an alphabetic character.");
     }
     let mut iden = String::new();
     iden.push(ch);
     
-    // Keep adding characters to the identifier.
+    // Keep adding characters to the identifier. After the first character,
+    // underscores are allowed too, e.g. UART_CR1.
     while let Some(&ch) = self.peek() {
-      if ch.is_alphabetic() || ch.is_numeric() {
+      if ch.is_alphabetic() || ch.is_numeric() || ch == '_' {
         iden.push(ch);
         self.next()?;
       } else {
@@ -195,9 +411,43 @@ impl<'l> Lexer<'l> {
       Some(kw) => Token::Keyw(kw),
       None => Token::Ident(iden),
     };
-    self.tokens.push(token);
+    self.push(token);
+    Ok(())
+  
+  }
+  
+  /// Lex a reference to an earlier result: '_' for the last one, or '$'
+  /// followed by its number.
+  fn lex_result(&mut self) -> LexResult<()> {
+    if self.next()? == '_' {
+      self.push(Token::Result(None));
+      return Ok(());
+    }
+    let mut num = String::new();
+    while let Some(&ch) = self.peek() {
+      if ch.is_ascii_digit() {
+        num.push(ch);
+        self.next()?;
+      } else {
+        break;
+      }
+    }
+    match num.parse::<usize>() {
+      Ok(n) => self.push(Token::Result(Some(n))),
+      Err(_) if num.is_empty() => return err!(self.span(), "Expected the number of a result after '$'."),
+      Err(e) => return err!(self.span(), &format!("Failed to parse ${} as a result number: {}", num, e)),
+    }
     Ok(())
+  }
   
+  /// Take the next character if it is ch. Returns whether it was.
+  fn follows(&mut self, ch: char) -> LexResult<bool> {
+    if self.peek() == Some(&ch) {
+      self.next()?;
+      Ok(true)
+    } else {
+      Ok(false)
+    }
   }
   
   fn lex_operator(&mut self) -> LexResult<()> {
@@ -207,36 +457,41 @@ impl<'l> Lexer<'l> {
     
       '+' => Oper(Plus),
       '-' => Oper(Minus),
-      '*' => Oper(Times),
+      '*' => if self.follows('*')? { Oper(Power) } else { Oper(Times) },
       '/' => Oper(Divide),
-      '&' => Oper(BitAnd),
-      '|' => Oper(BitOr),
+      '%' => Oper(Modulo),
       '^' => Oper(BitXor),
-      '!' => Oper(BitNeg),
+      
+      '&' => if self.follows('&')? { Oper(LogAnd) } else { Oper(BitAnd) },
+      '|' => if self.follows('|')? { Oper(LogOr) } else { Oper(BitOr) },
+      '!' => if self.follows('=')? { Oper(NotEq) } else { Oper(BitNeg) },
+      '=' => if self.follows('=')? { Oper(Eq) } else { Equals },
       
       '<' => {
-        if let Some(&'<') = self.peek() {
-          self.next()?;
-          Oper(BitShLeft)
+        if self.follows('<')? {
+          if self.follows('<')? { Oper(BitRotLeft) } else { Oper(BitShLeft) }
+        } else if self.follows('=')? {
+          Oper(LessEq)
         } else {
-          return err!("Error while lexing '<' (did you mean '<<'?)")
+          Oper(Less)
         }
       },
       
       '>' => {
-        if let Some(&'>') = self.peek() {
-          self.next()?;
-          Oper(BitShRight)
+        if self.follows('>')? {
+          if self.follows('>')? { Oper(BitRotRight) } else { Oper(BitShRight) }
+        } else if self.follows('=')? {
+          Oper(GreaterEq)
         } else {
-          return err!("Error while lexing '>' (did you mean '>>'?)");
+          Oper(Greater)
         }
       },
         
-      _ => return err!("Error while lexing operator"),
+      _ => return err!(self.span(), "Error while lexing operator"),
        
       };
       
-    self.tokens.push(token);
+    self.push(token);
     Ok(())
   }
   
@@ -0,0 +1,120 @@
+//! A calculator for bitwise operations on fixed-width words.
+//!
+//! Programs are parsed from strings, evaluated against a `Context` which
+//! holds the variables, functions and settings, and their results are shown
+//! with a `Display`:
+//!
+//!     extern crate bitshift;
+//!
+//!     use bitshift::{Context, Display};
+//!     use bitshift::width::Width;
+//!
+//!     let mut ctx = Context::new(Width::new(8).unwrap(), false);
+//!     let prog = bitshift::parse("(5 + 7) & !7").unwrap();
+//!     let result = bitshift::eval(&mut ctx, &prog).unwrap().unwrap();
+//!     assert_eq!(bitshift::format(&ctx, &Display::default(), result), "00001000 (8)");
+
+extern crate roxmltree;
+extern crate serde_json;
+
+pub mod ast;
+pub mod builtins;
+pub mod display;
+pub mod eval;
+pub mod float;
+pub mod layout;
+pub mod lexer;
+pub mod parser;
+pub mod regmap;
+pub mod session;
+pub mod span;
+pub mod width;
+
+pub use ast::Prog;
+pub use display::Display;
+pub use eval::{Context, eval};
+
+use eval::EvalError;
+use lexer::LexError;
+use parser::ParseError;
+use span::Span;
+use std::error;
+use std::fmt;
+
+/// Anything that can go wrong between reading a program and evaluating it.
+#[derive(Debug)]
+pub enum Error {
+  Lex(LexError),
+  Parse(ParseError),
+  Eval(EvalError),
+}
+
+impl Error {
+
+  /// The part of the input where things went wrong, if it's known.
+  pub fn span(&self) -> Option<Span> {
+    match *self {
+      Error::Lex(ref e) => Some(e.span()),
+      Error::Parse(ref e) => Some(e.span()),
+      Error::Eval(ref e) => e.span(),
+    }
+  }
+
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::Lex(ref e) => write!(f, "{}", e),
+      Error::Parse(ref e) => write!(f, "{}", e),
+      Error::Eval(ref e) => write!(f, "{}", e),
+    }
+  }
+}
+
+impl error::Error for Error {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match *self {
+      Error::Lex(ref e) => Some(e),
+      Error::Parse(ref e) => Some(e),
+      Error::Eval(ref e) => Some(e),
+    }
+  }
+}
+
+impl From<LexError> for Error {
+  fn from(e: LexError) -> Error {
+    Error::Lex(e)
+  }
+}
+
+impl From<ParseError> for Error {
+  fn from(e: ParseError) -> Error {
+    Error::Parse(e)
+  }
+}
+
+impl From<EvalError> for Error {
+  fn from(e: EvalError) -> Error {
+    Error::Eval(e)
+  }
+}
+
+/// Lex and parse a program, such as "let x = 15" or "x & !7".
+pub fn parse(input: &str) -> Result<Prog, Error> {
+  let tokens = lexer::lex(input)?;
+  Ok(parser::parse(&tokens)?)
+}
+
+/// Parse a program and evaluate it. Returns the result, or nothing if the
+/// program defined a function.
+pub fn run(ctx: &mut Context, input: &str) -> Result<Option<u128>, Error> {
+  let prog = parse(input)?;
+  Ok(eval(ctx, &prog)?)
+}
+
+/// Show a word the way the display settings say to, at the width of the
+/// context and signed if the context is.
+pub fn format(ctx: &Context, display: &Display, x: u128) -> String {
+  display.show(x, ctx.width(), ctx.signed())
+}
@@ -1,72 +1,218 @@
 
-mod ast;
-mod eval;
-mod lexer;
-mod parser;
+extern crate bitshift;
+extern crate rustyline;
 
-use ast::Expr;
-use eval::{Context, eval};
+mod editor;
+
+use bitshift::Context;
+use bitshift::eval::Overflow;
+use bitshift::session::{Session, describe_words, parse_overflow, parse_width};
+use bitshift::width::Width;
+use editor::LineEditor;
+use std::env;
+use std::fs::File;
 use std::io;
-use std::io::Write;
+use std::io::{BufRead, BufReader, IsTerminal};
+use std::path::PathBuf;
+use std::process;
+
+/// Where the input comes from.
+enum Source {
+  /// Expressions given on the command line with -e.
+  Exprs(Vec<String>),
+  /// A script file, with one program per line.
+  Script(String),
+  /// Standard input, which is interactive if it's a terminal.
+  Stdin,
+}
+
+/// Options given on the command line.
+struct Options {
+  width: Width,
+  signed: bool,
+  overflow: Overflow,
+  trace: bool,
+  rc: bool,
+  source: Source,
+}
 
-/// Produce the string of 1s and 0s representing this number in binary.
-fn as_binary_string(x: u16) -> String {
-  use std::cmp::min;
-  let mut s = String::with_capacity(16);
-  for i in (0..16).rev() {
-    let digit = min(1, 2_u16.pow(i) & x);
-    s.push_str(&format!("{}", digit));
+const USAGE: &str = "Usage: bitshift [-w|--width <bits>] [-s|--signed] [-o|--overflow <policy>] \
+[-t|--trace] [--no-rc] [-e <expr>]... [script]";
+
+/// Read the command line arguments.
+fn parse_args() -> Result<Options, String> {
+  let mut opts = Options {
+    width: Width::default(),
+    signed: false,
+    overflow: Overflow::Wrapping,
+    trace: false,
+    rc: true,
+    source: Source::Stdin,
+  };
+  let mut exprs = Vec::new();
+  let mut script = None;
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-w" | "--width" => {
+        let bits = args.next().ok_or(format!("Expected a width after '{}'.", arg))?;
+        opts.width = parse_width(&bits)?;
+      },
+      "-s" | "--signed" => opts.signed = true,
+      "-o" | "--overflow" => {
+        let policy = args.next().ok_or(format!("Expected an overflow policy after '{}'.", arg))?;
+        opts.overflow = parse_overflow(&policy)?;
+      },
+      "-t" | "--trace" => opts.trace = true,
+      "--no-rc" => opts.rc = false,
+      "-e" | "--eval" => {
+        let expr = args.next().ok_or(format!("Expected an expression after '{}'.", arg))?;
+        exprs.push(expr);
+      },
+      _ if arg.starts_with('-') => return Err(format!("Unrecognised argument '{}'.", arg)),
+      _ if script.is_none() => script = Some(arg),
+      _ => return Err(format!("Unexpected argument '{}'. Only one script can be run.", arg)),
+    }
   }
-  s
+  opts.source = match (exprs.is_empty(), script) {
+    (false, None) => Source::Exprs(exprs),
+    (true, Some(script)) => Source::Script(script),
+    (true, None) => Source::Stdin,
+    (false, Some(_)) => return Err("Can't use -e and run a script at the same time.".to_string()),
+  };
+  Ok(opts)
 }
 
-fn main() {
-  
+/// The file that is loaded whenever the calculator starts interactively.
+fn rc_path() -> Option<PathBuf> {
+  env::var_os("HOME").map(|home| PathBuf::from(home).join(".bitshiftrc"))
+}
+
+/// Print the output of a line of input.
+fn print_lines(lines: &[String]) {
+  for line in lines {
+    println!("{}", line);
+  }
+}
+
+/// Run each line of input in turn, stopping at the first error. The error is
+/// reported along with where it happened. Returns whether every line ran.
+fn run_lines<I>(session: &mut Session, name: &str, lines: I) -> bool
+  where I: Iterator<Item = io::Result<String>>
+{
+  for (i, line) in lines.enumerate() {
+    let line = match line {
+      Ok(line) => line,
+      Err(e) => {
+        eprintln!("{}: {}", name, e);
+        return false;
+      },
+    };
+    if line.trim() == "exit" {
+      break;
+    }
+    match session.run(&line) {
+      Ok(output) => print_lines(&output),
+      Err(e) => {
+        eprintln!("{}:{}: {}", name, i + 1, e.report(&line));
+        return false;
+      },
+    }
+  }
+  true
+}
+
+/// Run the interactive loop until the user types 'exit' or input ends.
+fn repl(session: &mut Session) {
+
   println!("Welcome to the bitshift calculator.");
-  println!("Numbers are displayed as 16-bit unsigned integers.");
+  println!("{}", describe_words(&session.ctx));
   println!("Assign to variables like so: 'let x = 15'.");
-  println!("Type 'exit' when you're done.");
+  println!("Change the word width like so: ':width 32'.");
+  println!("Switch signed mode on and off like so: ':signed on'.");
+  println!("Choose what happens on overflow like so: ':overflow checked'.");
+  println!("Show each step of the evaluation like so: ':trace on'.");
+  println!("Change how numbers are shown like so: ':format hex', or with a suffix: '7 :hex'.");
+  println!("Group binary digits like so: ':group nibble', and number them with ':ruler on'.");
+  println!("Call builtin functions like so: 'popcount(x)'. List them with ':functions'.");
+  println!("Define your own functions like so: 'fn roundup8(x) = (x + 7) & !7'.");
+  println!("Type ':help' to list every command, and 'exit' when you're done.");
   
-  let mut ctx = Context::new();
-  loop {
+  let mut editor = match LineEditor::new() {
+    Ok(editor) => editor,
+    Err(e) => {
+      eprintln!("Couldn't start the line editor: {}", e);
+      process::exit(1);
+    },
+  };
+  session.history = editor.history();
   
-    // Get the next line of input.
-    let mut input = String::new();  
-    print!("$ ");
-    io::stdout().flush();
-    if let Err(e) = io::stdin().read_line(&mut input) {
-      println!("{}", e);
-    }
+  // Get each line of input, stopping when there is no more.
+  while let Some(input) = editor.read_line("$ ", &session.ctx) {
     let input = input.trim();
     if input == "exit" {
       break;
     }
     
-    // Lex the program.
-    let mut tokens = lexer::lex(input);
-    if let Err(e) = tokens {
-      println!("{}", e);
-      continue;
-    }
-    let mut tokens = tokens.unwrap();
-    
-    // Parse the program.
-    let prog = parser::parse(&mut tokens);
-    if let Err(e) = prog {
-      println!("Error: {}", e);
-      continue;
-    }
-    let prog = prog.unwrap();
-    
-    // Print the result, if there is one.
-    let result = eval(&mut ctx, &prog);
-    if let Err(e) = result {
-      println!("Error: {}", e);
-      continue;
+    match session.run(input) {
+      Ok(output) => print_lines(&output),
+      Err(e) => println!("{}", e.report(input)),
     }
-    let result = result.unwrap();
-    println!("{} ({})", as_binary_string(result), result);
-    
+  }
+  editor.save_history();
+
+}
+
+fn main() {
+  
+  let opts = match parse_args() {
+    Ok(opts) => opts,
+    Err(e) => {
+      eprintln!("{}", e);
+      eprintln!("{}", USAGE);
+      process::exit(2);
+    },
+  };
+  let mut ctx = Context::new(opts.width, opts.signed);
+  ctx.set_overflow(opts.overflow);
+  ctx.set_tracing(opts.trace);
+  let mut session = Session::new(ctx);
+  
+  // Run the input. Without a terminal to talk to, there's no prompt or
+  // welcome message, and the first error ends the program.
+  let ok = match opts.source {
+    Source::Exprs(exprs) => {
+      run_lines(&mut session, "-e", exprs.into_iter().map(Ok))
+    },
+    Source::Script(path) => {
+      match File::open(&path) {
+        Ok(file) => run_lines(&mut session, &path, BufReader::new(file).lines()),
+        Err(e) => {
+          eprintln!("{}: {}", path, e);
+          process::exit(2);
+        },
+      }
+    },
+    Source::Stdin if io::stdin().is_terminal() => {
+      // Load the rc file first, so that the functions and variables it
+      // defines can be used straight away. It's fine if there isn't one.
+      // Scripts and -e don't load it, so they run the same for everyone.
+      if let Some(path) = rc_path().filter(|_| opts.rc).filter(|path| path.is_file()) {
+        if let Err(e) = session.load(&path.to_string_lossy()) {
+          eprintln!("{}", e.report(""));
+        }
+      }
+      session.interactive = true;
+      repl(&mut session);
+      true
+    },
+    Source::Stdin => {
+      let stdin = io::stdin();
+      run_lines(&mut session, "<stdin>", stdin.lock().lines())
+    },
+  };
+  if !ok {
+    process::exit(1);
   }
 
 }
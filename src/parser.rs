@@ -1,29 +1,34 @@
 
-use ast::Expr;
+use ast::{BinOp, Expr, ExprKind, UnaryOp};
 use ast::Prog;
 use lexer::{Keyword, Token, Operator};
-use std::collections::HashMap;
+use span::Span;
 use std::error::Error;
 use std::fmt;
-use std::iter::Peekable;
-use std::slice::Iter;
 
 #[derive(Debug)]
 pub struct ParseError {
   msg: String,
+  span: Span,
 }
 
 macro_rules! err {
-  ($msg:expr) => (Err(ParseError::new($msg)));
+  ($span:expr, $msg:expr) => (Err(ParseError::new($span, $msg)));
 }
 
 impl ParseError {
 
-  fn new(msg:&str) -> ParseError {
+  fn new(span: Span, msg:&str) -> ParseError {
     ParseError {
       msg: msg.to_string(),
+      span,
     }
   }
+  
+  /// The part of the input where the error happened.
+  pub fn span(&self) -> Span {
+    self.span
+  }
 
 }
 
@@ -41,311 +46,499 @@ impl Error for ParseError {
 
 type ParseResult<T> = Result<T, ParseError>;
 
-pub fn parse(tokens: &mut Vec<Token>) -> ParseResult<Prog> {
-  let mut parser = Parser::new(&tokens);
+pub fn parse(tokens: &[(Token, Span)]) -> ParseResult<Prog> {
+  let mut parser = Parser::new(tokens);
   parser.parse()
 }
 
-/// Use the shunting yard algorithm to convert infix notation into prefix
-/// notation. For example, a + b becomes + a b. 
-fn shunting_yard<'l>(tokens: &mut [Token]) -> ParseResult<Vec<Token>> {
-
-  use self::Token::*;
+/// The binary operator an operator token stands for, and its priority. A
+/// higher priority means it binds more tightly. The order of precedence is
+/// based on C, and every binary operator except ** groups to the left, so
+/// a - b - c means (a - b) - c but a ** b ** c means a ** (b ** c). Since **
+/// binds more tightly than prefix operators, -a ** b means -(a ** b).
+fn binary_op(op: &Operator) -> Option<(BinOp, u32)> {
   use self::Operator::*;
-  
-  // Build a map of operators to their priority. A higher priority means it
-  // binds more tightly. The order of precedence is based on C.
-  let mut priority = HashMap::new();
-  priority.insert(BitOr, 8);
-  priority.insert(BitXor, 10); 
-  priority.insert(BitAnd, 12);
-  priority.insert(BitShRight, 15);
-  priority.insert(BitShLeft, 15);
-  priority.insert(Plus, 20);
-  priority.insert(Minus, 20);
-  priority.insert(Times, 30);
-  priority.insert(Divide, 30);
-  priority.insert(BitNeg, 40);
-  
-  // We want to treat the entire expression as being enclosed in brackets. To
-  // do this, make the stack start with a right bracket on it, and perform one
-  // more "pop left bracket" operation after this main loop.
-  let mut output: Vec<Token> = Vec::new();
-  let mut stack: Vec<Token> = Vec::new();
-  stack.push(RightParen);
-  
-  for token in tokens.iter().rev() {
-    match token.clone() {
-    
-      // These tokens are not allowed in an expression.
-      Keyw(k) => return err!(&format!("keyword '{:?}' found while parsing expression.", k)),
-      Equals => return err!("equality sign '=' found while parsing expression."),
-      
-      Ident(_) | Num(_) => output.push(token.clone()),
-      
-      RightParen => stack.push(token.clone()),
-      
-      LeftParen => {
-        loop {
-          let top = stack.pop().expect("Mismatched brackets, expected right paren.");
-          match top {
-            Oper(_) => output.push(top.clone()),
-            RightParen => break,
-            _ => return err!("mismatched brackets, expected right paren."),
-          };
-        };
-      },
-      
-      // Pop all operators of higher precedence.
-      Oper(ref op) => {
-        while let Some(ref top) = stack.pop() {
-          match *top {
-            Oper(ref op2) => {
-              let p1 = priority.get(op)
-                .expect(&format!("No priority given for {:?}", op));
-              let p2 = priority.get(op2)
-                .expect(&format!("No priority given for {:?}", op2));
-              if p2 >= p1 {
-                output.push(top.clone());
-              } else {
-                stack.push(top.clone());
-                break;
-              };
-            },
-            
-            LeftParen | RightParen => {
-              stack.push(top.clone()); break;
-            }
-            
-            _ => return err!("Pushed non-bracket or non-operator on stack."),
-          }
-        };
-        stack.push(token.clone());
-      }
-    }
+  match *op {
+    LogOr       => Some((BinOp::LogOr, 4)),
+    LogAnd      => Some((BinOp::LogAnd, 6)),
+    BitOr       => Some((BinOp::BitOr, 8)),
+    BitXor      => Some((BinOp::BitXor, 10)),
+    BitAnd      => Some((BinOp::BitAnd, 12)),
+    Eq          => Some((BinOp::Eq, 13)),
+    NotEq       => Some((BinOp::NotEq, 13)),
+    Less        => Some((BinOp::Less, 14)),
+    LessEq      => Some((BinOp::LessEq, 14)),
+    Greater     => Some((BinOp::Greater, 14)),
+    GreaterEq   => Some((BinOp::GreaterEq, 14)),
+    BitShLeft   => Some((BinOp::BitShLeft, 15)),
+    BitShRight  => Some((BinOp::BitShRight, 15)),
+    BitRotLeft  => Some((BinOp::BitRotLeft, 15)),
+    BitRotRight => Some((BinOp::BitRotRight, 15)),
+    Plus        => Some((BinOp::Plus, 20)),
+    Minus       => Some((BinOp::Minus, 20)),
+    Times       => Some((BinOp::Times, 30)),
+    Divide      => Some((BinOp::Divide, 30)),
+    Modulo      => Some((BinOp::Modulo, 30)),
+    Power       => Some((BinOp::Power, 50)),
+    BitNeg      => None,
   }
+}
 
-  // Pretend there's an extra left paren at the end of the expression.
-  loop {
-    let top = stack.pop().expect("Mismatched brackets, expected right paren.");
-    match top {
-      Oper(_) => output.push(top.clone()),
-      RightParen => break,
-      _ => return err!("mismatched brackets, expected right paren."),
-    };
-  };
-
-  
-  output.reverse();
-  Ok(output) 
+/// The unary operator an operator token stands for when it comes before its
+/// operand.
+fn prefix_op(op: &Operator) -> Option<UnaryOp> {
+  match *op {
+    Operator::BitNeg => Some(UnaryOp::BitNeg),
+    Operator::Minus  => Some(UnaryOp::Negate),
+    _ => None,
+  }
 }
 
+/// The conditional operator c ? a : b binds less tightly than every binary
+/// operator, and groups to the right.
+const COND_PRIORITY: u32 = 2;
+
+/// Prefix operators bind more tightly than every binary operator.
+const PREFIX_PRIORITY: u32 = 40;
+
 struct Parser {
-  tokens: Vec<Token>,
+  tokens: Vec<(Token, Span)>,
   index: usize,
+  /// An empty span just after the last token, for errors about running out
+  /// of tokens.
+  end: Span,
 }
 
 impl Parser {
 
-  fn new(tokens: &Vec<Token>) -> Parser {
+  fn new(tokens: &[(Token, Span)]) -> Parser {
+    let end = tokens.last().map_or(0, |token| token.1.end);
     Parser {
-      tokens: tokens.clone(),
+      tokens: tokens.to_vec(),
       index: 0,
+      end: Span::new(end, end),
     }
   }
 
   /// Look at the next token, but don't advance the token stream.
-  fn peek(&mut self) -> ParseResult<Token> {
-    if self.done() {
-      err!("Expected token while peeking but found nothing.")
-    } else {
-      Ok(self.tokens[self.index].clone())
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.index).map(|token| &token.0)
+  }
+  
+  /// The span of the next token, or of the end of the input if there are no
+  /// tokens left.
+  fn span(&self) -> Span {
+    match self.tokens.get(self.index) {
+      Some(token) => token.1,
+      None => self.end,
     }
   }
   
   /// Check if the parser is at the end of the token stream.
-  fn done(&mut self) -> bool {
+  fn done(&self) -> bool {
     self.index >= self.tokens.len()
   }
   
-  /// Get the next token in the token stream, if it exists. Otherwise,
-  /// a ParseError is thrown.
-  fn next(&mut self) -> ParseResult<Token> {
+  /// Get the next token in the token stream, along with its span, if it
+  /// exists. Otherwise, a ParseError is thrown which says what was expected.
+  fn next(&mut self, expected: &str) -> ParseResult<(Token, Span)> {
     if self.done() {
-      err!("Expected token but found nothing.")
+      err!(self.end, &format!("Expected {} but found nothing.", expected))
     } else {
       self.index += 1;
       Ok(self.tokens[self.index - 1].clone())
     }
   }
   
-  /// Perform the shunting yard algorithm on the rest of the input to make it
-  /// adhere to the order of operations. The input vector will be transformed
-  /// in place.
-  ///
-  /// This is a little inefficient since it does a bit of copying.
-  fn shunting_yard(&mut self) -> ParseResult<()> {
-  
-    // Figure out how to reorder this expression.
-    let reordering;
-    {
-      let tokens_to_parse = &mut self.tokens[self.index..];
-      reordering = shunting_yard(tokens_to_parse)?;
+  /// Take the next token, which must be the given token.
+  fn expect(&mut self, token: Token, context: &str) -> ParseResult<Span> {
+    let expected = format!("{} {}", token, context);
+    let (tok, span) = self.next(&expected)?;
+    if tok == token {
+      Ok(span)
+    } else {
+      err!(span, &format!("Expected {} but found {}.", expected, tok))
     }
-    
-    // Copy new values over. Note that shunting yard strips the brackets, so
-    // reordering may not be the same length as self.tokens[self.index..].
-    let num_brackets_stripped = (self.tokens.len() - self.index) - reordering.len();
-    for i in 0..reordering.len() {
-      self.tokens[self.index + i] = reordering[i].clone();
+  }
+  
+  /// Look at the next token, and if it's a binary operator, find out which
+  /// one it is and its priority.
+  fn peek_binary_op(&self) -> Option<(BinOp, u32)> {
+    match self.peek() {
+      Some(Token::Oper(op)) => binary_op(op),
+      _ => None,
     }
-    
-    // Pop off the last few entries. The number to pop is the number of brackets
-    // that were stripped by shunting.
-    for i in 0..num_brackets_stripped {
-      self.tokens.pop();
+  }
+  
+  /// Take the next token if it is the given token. Returns whether it was.
+  fn eat(&mut self, token: &Token) -> bool {
+    if self.peek() == Some(token) {
+      self.index += 1;
+      true
+    } else {
+      false
     }
-    Ok(())
   }
 
-  /// Parse a program, which is either a single assignment or an expression.
+  /// Parse a program, which is either a single assignment, a function
+  /// definition or an expression.
   fn parse(&mut self) -> ParseResult<Prog> {
-    let token = self.peek()?.clone();
-    let prog = match token {
+    let prog = match self.peek() {
     
       // An assignment.
-      Token::Keyw(Keyword::Let) => {
-        self.next()?;
+      Some(&Token::Keyw(Keyword::Let)) => {
+        self.index += 1;
+        let mut span = self.span();
         let name = self.parse_ident()?;
-        if self.peek()? != Token::Equals {
-          return err!("Expected '=' while parsing assignment.");
+        if self.peek() == Some(&Token::LeftBracket) {
+          let (hi, lo, _) = self.parse_slice()?;
+          self.expect(Token::Equals, "after the bits of the variable")?;
+          let expr = self.parse_expr()?;
+          Prog::AssignSlice(name, span, hi, lo, expr)
+        } else {
+          let layout = if self.eat(&Token::Colon) {
+            span = span.to(self.span());
+            Some(self.parse_ident()?)
+          } else {
+            None
+          };
+          self.expect(Token::Equals, "after the name of the variable")?;
+          let expr = self.parse_expr()?;
+          Prog::Assign(name, span, layout, expr)
         }
-        self.next()?;
-        self.shunting_yard()?;
-        let expr = self.parse_expr()?;
-        Prog::Assign(name, expr)
       },
       
-      // An expression.
-      _ => {
-        self.shunting_yard()?;
-        Prog::Expression(self.parse_expr()?)
+      // A function definition.
+      Some(&Token::Keyw(Keyword::Fn)) => {
+        self.index += 1;
+        let name = self.parse_ident()?;
+        let params = self.parse_params()?;
+        self.expect(Token::Equals, "after the parameters of the function")?;
+        let body = self.parse_expr()?;
+        Prog::Function(name, params, body)
       },
+      
+      // A layout.
+      Some(&Token::Keyw(Keyword::Layout)) => {
+        self.index += 1;
+        let name = self.parse_ident()?;
+        let fields = self.parse_fields()?;
+        Prog::Layout(name, fields)
+      },
+      
+      // An expression.
+      _ => Prog::Expression(self.parse_expr()?),
     
     };
 
     // Check we are at the end of the program.
-    if !self.done() {
-      return err!(&format!("Extra token {:?} found after program {:?}",
-                  self.peek().unwrap(), prog));
+    if let Some(tok) = self.peek() {
+      return err!(self.span(), &format!("Unexpected {} after the end of the program.", tok));
     }
     Ok(prog)
   }
   
-  /// Parse an expression, which could be a constant, variable,
-  /// a unary operator, or a binary operator.
+  /// Parse an expression.
   fn parse_expr(&mut self) -> ParseResult<Expr> {
+    self.parse_binary(0)
+  }
+  
+  /// Parse an expression made of operands joined by binary operators, which
+  /// stops at the first binary operator with a priority lower than min.
+  fn parse_binary(&mut self, min: u32) -> ParseResult<Expr> {
+    let mut lhs = self.parse_prefix()?;
+    loop {
+      if let Some((op, priority)) = self.peek_binary_op() {
+        if priority < min {
+          break;
+        }
+        self.index += 1;
+        
+        // The right operand can't contain operators with the same priority,
+        // or they'd group to the right. That's what ** wants, though.
+        let rhs = match op {
+          BinOp::Power => self.parse_binary(priority)?,
+          _ => self.parse_binary(priority + 1)?,
+        };
+        let span = lhs.span.to(rhs.span);
+        lhs = Expr::new(ExprKind::BinaryOper(op, Box::new(lhs), Box::new(rhs)), span);
+      } else if self.peek() == Some(&Token::Question) && COND_PRIORITY >= min {
+        lhs = self.parse_cond(lhs)?;
+      } else {
+        break;
+      }
+    }
+    Ok(lhs)
+  }
+  
+  /// Parse the rest of a conditional expression c ? a : b, given c. As in C,
+  /// anything can go between the '?' and ':', and the expression after the
+  /// ':' extends to the end of the expression, grouping to the right, so
+  /// c ? a : d ? b : e is c ? a : (d ? b : e).
+  fn parse_cond(&mut self, cond: Expr) -> ParseResult<Expr> {
+    self.expect(Token::Question, "in a conditional expression")?;
+    let e1 = self.parse_expr()?;
+    self.expect(Token::Colon, "after the first branch of a conditional expression")?;
+    let e2 = self.parse_binary(COND_PRIORITY)?;
+    let span = cond.span.to(e2.span);
+    Ok(Expr::new(ExprKind::Cond(Box::new(cond), Box::new(e1), Box::new(e2)), span))
+  }
+  
+  /// Parse an operand along with any slices of it.
+  fn parse_prefix(&mut self) -> ParseResult<Expr> {
+    let mut expr = self.parse_operand()?;
     
-    let tok = self.peek()?.clone();
-    
+    // Any number of slices and fields can follow an operand, e.g. x[7:0][3]
+    // or v.mode[0].
+    loop {
+      if self.peek() == Some(&Token::LeftBracket) {
+        let (hi, lo, end) = self.parse_slice()?;
+        let span = expr.span.to(end);
+        expr = Expr::new(ExprKind::Slice(Box::new(expr), Box::new(hi), lo.map(Box::new)), span);
+      } else if self.eat(&Token::Dot) {
+        let end = self.span();
+        let field = self.parse_ident()?;
+        let span = expr.span.to(end);
+        expr = Expr::new(ExprKind::Field(Box::new(expr), field), span);
+      } else {
+        return Ok(expr);
+      }
+    }
+  }
+  
+  /// Parse an operand, which is a constant, a variable, a function call, an
+  /// expression in brackets, or a prefix operator applied to an operand.
+  fn parse_operand(&mut self) -> ParseResult<Expr> {
+    let (tok, span) = self.next("an expression")?;
     match tok {
       
-      Token::Ident(ref name) => {
-        self.next()?;
-        Ok(Expr::Var(name.clone()))
+      Token::Ident(name) => {
+        if self.peek() == Some(&Token::LeftParen) {
+          let (args, end) = self.parse_args()?;
+          Ok(Expr::new(ExprKind::Call(name, args), span.to(end)))
+        } else {
+          Ok(Expr::new(ExprKind::Var(name), span))
+        }
       },
       
-      Token::Num(num) => {
-        self.next()?;
-        Ok(Expr::Const(num))
+      Token::Num(num) => Ok(Expr::new(ExprKind::Const(num), span)),
+      
+      Token::Float(num) => match num.parse() {
+        Ok(val) => Ok(Expr::new(ExprKind::Float(val), span)),
+        Err(_) => err!(span, &format!("Failed to parse {} as a float.", num)),
       },
       
-      Token::Oper(ref op) => {
-        use self::Operator::*;
-        match *op {
-          BitNeg => self.parse_uop(),
-          
-          Plus | Minus | Times | Divide |
-          BitAnd | BitOr | BitXor |
-          BitShLeft | BitShRight => self.parse_bop(),
-        }
-      }
+      Token::Result(n) => Ok(Expr::new(ExprKind::Result(n), span)),
       
-      Token::LeftParen | Token::RightParen => 
-        err!("Found left paren and right paren while parsing, but these /
-              should have been eliminated during shunting yard phase."),
+      Token::LeftBrace => self.parse_concat(span),
       
-      Token::Equals =>
-        err!("Illegal sign '=' found while parsing expression."),
-        
-      Token::Keyw(kw) =>
-        err!(&format!("Keyword '{:?}' found while parsing expression", kw)),
+      // The span of an expression in brackets includes the brackets.
+      Token::LeftParen => {
+        let mut expr = self.parse_expr()?;
+        let end = self.expect(Token::RightParen, "to close the bracket")?;
+        expr.span = span.to(end);
+        Ok(expr)
+      },
+      
+      Token::Oper(ref op) => match prefix_op(op) {
+        Some(uop) => {
+          let e = self.parse_binary(PREFIX_PRIORITY)?;
+          let span = span.to(e.span);
+          Ok(Expr::new(ExprKind::UnaryOper(uop, Box::new(e)), span))
+        },
+        None => err!(span, &format!("Expected an expression but found the binary operator {}.", tok)),
+      },
       
+      _ => err!(span, &format!("Expected an expression but found {}.", tok)),
+      
+    }
+  }
+  
+  /// Parse the bits of a slice in square brackets, which are either the high
+  /// and low bits separated by ':' or a single bit. Returns the span of the
+  /// closing bracket as well.
+  fn parse_slice(&mut self) -> ParseResult<(Expr, Option<Expr>, Span)> {
+    self.expect(Token::LeftBracket, "before the bits of a slice")?;
+    let hi = self.parse_expr()?;
+    let lo = if self.eat(&Token::Colon) {
+      Some(self.parse_expr()?)
+    } else {
+      None
+    };
+    let end = self.expect(Token::RightBracket, "after the bits of a slice")?;
+    Ok((hi, lo, end))
+  }
+  
+  /// Parse a concatenation {a, b, c} or a replication {n{a, b}}, given the
+  /// span of the opening brace.
+  fn parse_concat(&mut self, start: Span) -> ParseResult<Expr> {
+    let first = self.parse_expr()?;
+    if !self.eat(&Token::LeftBrace) {
+      let (items, end) = self.parse_items(first)?;
+      return Ok(Expr::new(ExprKind::Concat(items), start.to(end)));
+    }
+    let item = self.parse_expr()?;
+    let (items, _) = self.parse_items(item)?;
+    let end = self.expect(Token::RightBrace, "after a replication")?;
+    Ok(Expr::new(ExprKind::Replicate(Box::new(first), items), start.to(end)))
+  }
+  
+  /// Parse the rest of the expressions in braces, which are separated by
+  /// commas, given the first one. Returns the span of the closing brace as
+  /// well.
+  fn parse_items(&mut self, first: Expr) -> ParseResult<(Vec<Expr>, Span)> {
+    let mut items = vec![first];
+    loop {
+      let (tok, span) = self.next("',' or '}' after the expression")?;
+      match tok {
+        Token::Comma => items.push(self.parse_expr()?),
+        Token::RightBrace => return Ok((items, span)),
+        _ => return err!(span, &format!("Expected ',' or '}}' after the expression but found {}.", tok)),
+      }
+    }
+  }
+  
+  /// Parse the fields of a layout, which are names and numbers of bits
+  /// enclosed in braces and separated by commas, e.g. { en:1, mode:3 }.
+  fn parse_fields(&mut self) -> ParseResult<Vec<(String, u128)>> {
+    let mut fields: Vec<(String, u128)> = Vec::new();
+    self.expect(Token::LeftBrace, "before the fields of the layout")?;
+    loop {
+      let span = self.span();
+      let field = self.parse_ident()?;
+      if fields.iter().any(|(name, _)| *name == field) {
+        return err!(span, &format!("Field '{}' appears more than once.", field));
+      }
+      self.expect(Token::Colon, "after the name of the field")?;
+      let (tok, span) = self.next("the number of bits in the field")?;
+      match tok {
+        Token::Num(bits) if (1..=128).contains(&bits) => fields.push((field, bits)),
+        Token::Num(_) => return err!(span, "A field must have between 1 and 128 bits."),
+        _ => return err!(span, &format!("Expected the number of bits in the field but found {}.", tok)),
+      }
+      let (tok, span) = self.next("',' or '}' after the field")?;
+      match tok {
+        Token::Comma => (),
+        Token::RightBrace => return Ok(fields),
+        _ => return err!(span, &format!("Expected ',' or '}}' after the field but found {}.", tok)),
+      }
     }
   }
   
   /// Parse the next token as an identifier.
   fn parse_ident(&mut self) -> ParseResult<String> {
-    let tok = self.next()?.clone();
+    let (tok, span) = self.next("an identifier")?;
     match tok {
       Token::Ident(name) => Ok(name),
-      _ => err!(&format!("Wanted identifier but found {:?}", tok)),
+      _ => err!(span, &format!("Expected an identifier but found {}.", tok)),
     }
   }
   
-  /// Parse a unary operator and its arguments.
-  fn parse_uop(&mut self) -> ParseResult<Expr> {
-    use ast::UnaryOp;
-    let tok = self.next()?.clone();
-    match tok {
-      Token::Oper(op) =>
-        match op {
-          Operator::BitNeg => {
-            let e = self.parse_expr()?;
-            Ok(Expr::UnaryOper(UnaryOp::BitNeg, Box::new(e)))
-          },
-          _ => err!("Non-unary operator found while parsing unary operation."),
-        },
-      _ => err!("Non-operator found while parsing unary operation."),        
+  /// Parse the parameters of a function definition, which are identifiers
+  /// enclosed in brackets and separated by commas.
+  fn parse_params(&mut self) -> ParseResult<Vec<String>> {
+    let mut params: Vec<String> = Vec::new();
+    self.expect(Token::LeftParen, "before the parameters of the function")?;
+    if self.eat(&Token::RightParen) {
+      return Ok(params);
+    }
+    loop {
+      let span = self.span();
+      let param = self.parse_ident()?;
+      if params.contains(&param) {
+        return err!(span, &format!("Parameter '{}' appears more than once.", param));
+      }
+      params.push(param);
+      let (tok, span) = self.next("',' or ')' after the parameter")?;
+      match tok {
+        Token::Comma => (),
+        Token::RightParen => return Ok(params),
+        _ => return err!(span, &format!("Expected ',' or ')' after the parameter but found {}.", tok)),
+      }
     }
   }
   
-  /// Parse a binary operator and its arguments.
-  fn parse_bop(&mut self) -> ParseResult<Expr> {
-    use ast::BinOp;
-    let tok = self.next()?.clone();
-    match tok {
-    
-      Token::Oper(op) => {
-        let e1 = Box::new(self.parse_expr()?);
-        let e2 = Box::new(self.parse_expr()?);
-        match op {
-          Operator::Plus =>
-            Ok(Expr::BinaryOper(BinOp::Plus, e1, e2)),
-          Operator::Minus =>
-            Ok(Expr::BinaryOper(BinOp::Minus, e1, e2)),
-          Operator::Times =>
-            Ok(Expr::BinaryOper(BinOp::Times, e1, e2)),
-          Operator::Divide =>
-            Ok(Expr::BinaryOper(BinOp::Divide, e1, e2)),
-          Operator::BitShLeft =>
-            Ok(Expr::BinaryOper(BinOp::BitShLeft, e1, e2)),
-          Operator::BitShRight =>
-            Ok(Expr::BinaryOper(BinOp::BitShRight, e1, e2)),
-          Operator::BitAnd =>
-            Ok(Expr::BinaryOper(BinOp::BitAnd, e1, e2)),
-          Operator::BitOr =>
-            Ok(Expr::BinaryOper(BinOp::BitOr, e1, e2)),
-          Operator::BitXor =>
-            Ok(Expr::BinaryOper(BinOp::BitXor, e1, e2)),
-          _ =>
-            err!("Non-binary operator found while parsing binary operator."),
-        }
-      },
-      
-      _ => err!("Non-operator found while parsing binary operation."),
+  /// Parse the arguments of a function call, which are enclosed in brackets
+  /// and separated by commas. Also returns the span of the closing bracket.
+  fn parse_args(&mut self) -> ParseResult<(Vec<Expr>, Span)> {
+    let mut args = Vec::new();
+    self.expect(Token::LeftParen, "before the arguments of the function")?;
+    if self.peek() == Some(&Token::RightParen) {
+      let end = self.span();
+      self.index += 1;
+      return Ok((args, end));
+    }
+    loop {
+      args.push(self.parse_expr()?);
+      let (tok, span) = self.next("',' or ')' after the argument")?;
+      match tok {
+        Token::Comma => (),
+        Token::RightParen => return Ok((args, span)),
+        _ => return err!(span, &format!("Expected ',' or ')' after the argument but found {}.", tok)),
+      }
     }
   }
   
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use lexer::lex;
+
+  /// Parse an expression and write it back out with every bracket.
+  fn parse_expr(input: &str) -> String {
+    match parse(&lex(input).unwrap()).unwrap() {
+      Prog::Expression(expr) => format!("{:?}", expr),
+      prog => panic!("{} isn't an expression: {:?}", input, prog),
+    }
+  }
+
+  /// Parse some input that should fail, returning the error.
+  fn parse_err(input: &str) -> ParseError {
+    match parse(&lex(input).unwrap()) {
+      Ok(prog) => panic!("{} parsed as {:?}", input, prog),
+      Err(e) => e,
+    }
+  }
+
+  #[test]
+  fn precedence_and_grouping() {
+    assert_eq!(parse_expr("1 + 2 * 3"), "(+ 1 (* 2 3))");
+    assert_eq!(parse_expr("1 - 2 - 3"), "(- (- 1 2) 3)");
+    assert_eq!(parse_expr("1 + 7 % 4"), "(+ 1 (% 7 4))");
+    assert_eq!(parse_expr("2 ** 3 ** 2"), "(** 2 (** 3 2))");
+    assert_eq!(parse_expr("-2 ** 2"), "(- (** 2 2))");
+    assert_eq!(parse_expr("!x & 1 << 2"), "(& (! x) (<< 1 2))");
+    assert_eq!(parse_expr("!-x * 2"), "(* (! (- x)) 2)");
+    assert_eq!(parse_expr("1 - -2"), "(- 1 (- 2))");
+    assert_eq!(parse_expr("1 | 2 ^ 3 & 4"), "(| 1 (^ 2 (& 3 4)))");
+    assert_eq!(parse_expr("1 | 2 & 3 == 3"), "(| 1 (& 2 (== 3 3)))");
+    assert_eq!(parse_expr("a < b && c || d"), "(|| (&& (< a b) c) d)");
+    assert_eq!(parse_expr("a ? b : c ? d : e"), "(? a b (? c d e))");
+    assert_eq!(parse_expr("a ? b : c + 1"), "(? a b (+ c 1))");
+    assert_eq!(parse_expr("(1 | 2) * f(3, 4)"), "(* (| 1 2) f(3, 4))");
+  }
+
+  #[test]
+  fn unclosed_bracket() {
+    let e = parse_err("(1 + 2");
+    assert_eq!(e.span(), Span::new(6, 6));
+  }
+
+  #[test]
+  fn missing_operand() {
+    assert_eq!(parse_err("1 +").span(), Span::new(3, 3));
+    assert_eq!(parse_err("* 2").span(), Span::new(0, 1));
+  }
+
+  #[test]
+  fn unexpected_tokens() {
+    assert_eq!(parse_err("1 2").span(), Span::new(2, 3));
+    assert_eq!(parse_err("(1 + 2))").span(), Span::new(7, 8));
+    assert_eq!(parse_err("f(1,").span(), Span::new(4, 4));
+    parse_err("1 ? 2");
+    parse_err("let = 1");
+  }
+
+}
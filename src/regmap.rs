@@ -0,0 +1,500 @@
+
+use eval::Context;
+use layout::{Field, Layout};
+use roxmltree::{Document, Node};
+use serde_json::Value;
+use std::fs;
+use width::{Width, WIDTHS};
+
+/// The registers of a device, as described by a CMSIS-SVD file or a JSON
+/// file.
+///
+/// A JSON description looks like this, where numbers can also be strings
+/// such as "0x40011000":
+///
+/// ```text
+/// { "peripherals": [
+///     { "name": "UART", "base": "0x40011000", "registers": [
+///         { "name": "CR1", "offset": "0x0C", "size": 32, "reset": 0, "fields": [
+///             { "name": "TE", "offset": 3, "width": 1 } ] } ] } ] }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Device {
+  pub peripherals: Vec<Peripheral>,
+}
+
+/// A block of registers starting at a base address.
+#[derive(Clone, Debug)]
+pub struct Peripheral {
+  pub name: String,
+  pub base: u128,
+  pub registers: Vec<Register>,
+}
+
+/// A register at an offset from the base address of its peripheral.
+#[derive(Clone, Debug)]
+pub struct Register {
+  pub name: String,
+  pub offset: u128,
+  pub size: u32,
+  pub reset: u128,
+  pub fields: Vec<Field>,
+}
+
+/// Read a description of a device's registers from a file, which is either
+/// CMSIS-SVD or JSON.
+pub fn read(path: &str) -> Result<Device, String> {
+  let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+  if text.trim_start().starts_with('<') {
+    parse_svd(&text)
+  } else {
+    parse_json(&text)
+  }
+}
+
+/// Parse a number the way SVD files write them: in decimal, in hex after
+/// "0x", or in binary after "#".
+fn parse_number(s: &str) -> Result<u128, String> {
+  let s = s.trim();
+  let parsed = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+    u128::from_str_radix(hex, 16)
+  } else if let Some(bin) = s.strip_prefix('#') {
+    u128::from_str_radix(bin, 2)
+  } else {
+    s.parse::<u128>()
+  };
+  parsed.map_err(|_| format!("Expected a number but found '{}'.", s))
+}
+
+/// Parse a CMSIS-SVD file. Registers in clusters and arrays of registers
+/// aren't supported, and are left out.
+pub fn parse_svd(text: &str) -> Result<Device, String> {
+  let doc = Document::parse(text).map_err(|e| e.to_string())?;
+  let peripherals: Vec<Node> = doc.root_element()
+                                  .children()
+                                  .filter(|node| node.has_tag_name("peripherals"))
+                                  .flat_map(|node| node.children())
+                                  .filter(|node| node.has_tag_name("peripheral"))
+                                  .collect();
+  let mut device = Device { peripherals: Vec::new() };
+  for &peripheral in &peripherals {
+    let name = svd_text(peripheral, "name")?;
+    let base = parse_number(&svd_text(peripheral, "baseAddress")?)?;
+
+    // A peripheral can have the same registers as another one, at a different
+    // base address.
+    let mut source = peripheral;
+    if let Some(from) = peripheral.attribute("derivedFrom") {
+      if child(peripheral, "registers").is_none() {
+        source = *peripherals.iter()
+                             .find(|&&other| child(other, "name").and_then(|node| node.text()) == Some(from))
+                             .ok_or(format!("Peripheral {} is derived from {}, which isn't there.", name, from))?;
+      }
+    }
+    let mut registers = Vec::new();
+    if let Some(node) = child(source, "registers") {
+      for register in node.children().filter(|node| node.has_tag_name("register")) {
+        if child(register, "dim").is_none() {
+          registers.push(svd_register(register)?);
+        }
+      }
+    }
+    device.peripherals.push(Peripheral { name, base, registers });
+  }
+  Ok(device)
+}
+
+/// The first child element with this tag name.
+fn child<'a, 'input>(node: Node<'a, 'input>, tag: &str) -> Option<Node<'a, 'input>> {
+  node.children().find(|child| child.has_tag_name(tag))
+}
+
+/// The text of the child element with this tag name, which has to be there.
+fn svd_text(node: Node, tag: &str) -> Result<String, String> {
+  match child(node, tag).and_then(|child| child.text()) {
+    Some(text) => Ok(text.trim().to_string()),
+    None => Err(format!("Expected <{}> in <{}> on line {}.", tag, node.tag_name().name(),
+                        node.document().text_pos_at(node.range().start).row)),
+  }
+}
+
+/// The text of the child element with this tag name, or of the nearest
+/// element above it with one, since registers inherit their size and reset
+/// value from their peripheral and device.
+fn svd_inherited(node: Node, tag: &str) -> Option<String> {
+  node.ancestors()
+      .filter_map(|node| child(node, tag))
+      .find_map(|child| child.text())
+      .map(|text| text.trim().to_string())
+}
+
+fn svd_register(register: Node) -> Result<Register, String> {
+  let name = svd_text(register, "name")?;
+  let offset = parse_number(&svd_text(register, "addressOffset")?)?;
+  let size = match svd_inherited(register, "size") {
+    Some(size) => parse_number(&size)?,
+    None => 32,
+  };
+  let reset = match svd_inherited(register, "resetValue") {
+    Some(reset) => parse_number(&reset)?,
+    None => 0,
+  };
+  let mut fields = Vec::new();
+  if let Some(node) = child(register, "fields") {
+    for field in node.children().filter(|node| node.has_tag_name("field")) {
+      fields.push(svd_field(field)?);
+    }
+  }
+  Ok(Register { name, offset, size: size.min(128) as u32, reset, fields })
+}
+
+/// Parse a field, whose bits are given by bitOffset and bitWidth, by lsb and
+/// msb, or by a bitRange like "[7:4]".
+fn svd_field(field: Node) -> Result<Field, String> {
+  let name = svd_text(field, "name")?;
+  let (lo, hi) = if child(field, "bitOffset").is_some() {
+    let lo = parse_number(&svd_text(field, "bitOffset")?)?;
+    let bits = match child(field, "bitWidth") {
+      Some(_) => parse_number(&svd_text(field, "bitWidth")?)?,
+      None => 1,
+    };
+    (lo, lo.saturating_add(bits.max(1) - 1))
+  } else if child(field, "lsb").is_some() {
+    (parse_number(&svd_text(field, "lsb")?)?, parse_number(&svd_text(field, "msb")?)?)
+  } else {
+    let range = svd_text(field, "bitRange")?;
+    let bounds: Vec<&str> = range.trim_matches(|ch| ch == '[' || ch == ']').split(':').collect();
+    if bounds.len() != 2 {
+      return Err(format!("Expected a bit range like [7:4] for field {} but found '{}'.", name, range));
+    }
+    (parse_number(bounds[1])?, parse_number(bounds[0])?)
+  };
+  make_field(name, lo, hi)
+}
+
+/// Make a field with the bits from lo up to hi.
+fn make_field(name: String, lo: u128, hi: u128) -> Result<Field, String> {
+  if hi < lo || hi >= 128 {
+    return Err(format!("Field {} has the bits from {} up to {}, which isn't possible.", name, lo, hi));
+  }
+  Ok(Field { name, lo: lo as u32, bits: (hi - lo + 1) as u32 })
+}
+
+/// Parse a JSON description of a device.
+pub fn parse_json(text: &str) -> Result<Device, String> {
+  let json: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+  let mut device = Device { peripherals: Vec::new() };
+  for peripheral in json_array(&json, "peripherals")? {
+    let mut registers = Vec::new();
+    for register in json_array(peripheral, "registers")? {
+      let mut fields = Vec::new();
+      for field in json_array(register, "fields")? {
+        let name = json_string(field, "name")?;
+        let lo = json_number(field, "offset", None)?;
+        let bits = json_number(field, "width", Some(1))?.max(1);
+        fields.push(make_field(name, lo, lo.saturating_add(bits - 1))?);
+      }
+      registers.push(Register {
+        name: json_string(register, "name")?,
+        offset: json_number(register, "offset", None)?,
+        size: json_number(register, "size", Some(32))?.min(128) as u32,
+        reset: json_number(register, "reset", Some(0))?,
+        fields,
+      });
+    }
+    device.peripherals.push(Peripheral {
+      name: json_string(peripheral, "name")?,
+      base: json_number(peripheral, "base", None)?,
+      registers,
+    });
+  }
+  Ok(device)
+}
+
+/// The elements of an array in a JSON object. A missing array is empty.
+fn json_array<'a>(json: &'a Value, key: &str) -> Result<&'a [Value], String> {
+  match json.get(key) {
+    Some(Value::Array(items)) => Ok(items),
+    None => Ok(&[]),
+    Some(_) => Err(format!("Expected \"{}\" to be an array.", key)),
+  }
+}
+
+fn json_string(json: &Value, key: &str) -> Result<String, String> {
+  match json.get(key) {
+    Some(Value::String(s)) => Ok(s.clone()),
+    _ => Err(format!("Expected \"{}\" to be a string in {}.", key, json)),
+  }
+}
+
+/// A number in a JSON object, which can be written as a string to use hex.
+/// If there's a default, the number can be left out.
+fn json_number(json: &Value, key: &str, default: Option<u128>) -> Result<u128, String> {
+  match (json.get(key), default) {
+    (Some(Value::Number(n)), _) => n.as_u64()
+                                    .map(u128::from)
+                                    .ok_or(format!("Expected \"{}\" to be a whole number in {}.", key, json)),
+    (Some(Value::String(s)), _) => parse_number(s),
+    (None, Some(default)) => Ok(default),
+    _ => Err(format!("Expected \"{}\" to be a number in {}.", key, json)),
+  }
+}
+
+impl Device {
+
+  /// Define constants for every register in the context, named after the
+  /// peripheral and register as in CMSIS headers. For a register CR1 of
+  /// peripheral UART, these are:
+  ///
+  /// - UART_BASE, the base address of the peripheral.
+  /// - UART_CR1, the reset value of the register, with a layout called
+  ///   UART_CR1 if the register has fields.
+  /// - UART_CR1_Offset and UART_CR1_Addr, the offset and address of the
+  ///   register.
+  /// - UART_CR1_TE_Pos, the lowest bit of field TE, and UART_CR1_TE_Msk or
+  ///   just UART_CR1_TE, the mask of its bits.
+  ///
+  /// Every name has to be one that can be typed, and every number and layout
+  /// has to fit in a word. Nothing is defined unless everything can be.
+  /// Returns how many registers there are.
+  pub fn define(&self, ctx: &mut Context) -> Result<usize, String> {
+    let mut consts = Vec::new();
+    let mut layouts = Vec::new();
+    for peripheral in &self.peripherals {
+      check_name("Peripheral", &peripheral.name)?;
+      consts.push((format!("{}_BASE", peripheral.name), peripheral.base));
+      for register in &peripheral.registers {
+        check_name("Register", &register.name)?;
+        let name = format!("{}_{}", peripheral.name, register.name);
+        consts.push((name.clone(), register.reset));
+        consts.push((format!("{}_Offset", name), register.offset));
+        consts.push((format!("{}_Addr", name), peripheral.base.saturating_add(register.offset)));
+        for field in &register.fields {
+          check_name("Field", &field.name)?;
+          let mask = field.extract(u128::MAX) << field.lo;
+          consts.push((format!("{}_{}_Pos", name, field.name), u128::from(field.lo)));
+          consts.push((format!("{}_{}_Msk", name, field.name), mask));
+          consts.push((format!("{}_{}", name, field.name), mask));
+        }
+        if !register.fields.is_empty() {
+          let fields = layout_fields(&name, register)?;
+          Layout::new(&name, &fields)?.fits(ctx.width())?;
+          layouts.push((name.clone(), fields));
+        }
+      }
+    }
+
+    // Check every number fits before changing the context. The layouts have
+    // already been checked.
+    let width = ctx.width();
+    if let Some(&(ref name, val)) = consts.iter().find(|&&(_, val)| !width.fits(val)) {
+      let largest = consts.iter().map(|&(_, val)| val).max().unwrap_or(0);
+      let needed = WIDTHS.iter().find(|&&bits| Width::new(bits).is_some_and(|width| width.fits(largest)));
+      return Err(format!("{} is {:#x}, which doesn't fit in a {}-bit word. Change the width with ':width {}' first.",
+                         name, val, width, needed.unwrap_or(&128)));
+    }
+    for (name, fields) in layouts {
+      ctx.define_layout(&name, &fields).map_err(|e| e.to_string())?;
+      ctx.set_layout(&name, &name).map_err(|e| e.to_string())?;
+    }
+    for (name, val) in consts {
+      ctx.insert(&name, val);
+    }
+    Ok(self.peripherals.iter().map(|peripheral| peripheral.registers.len()).sum())
+  }
+
+}
+
+/// Check that a name from a file is one that can be typed: a letter followed
+/// by letters, digits and underscores.
+fn check_name(kind: &str, name: &str) -> Result<(), String> {
+  let mut chars = name.chars();
+  let valid = chars.next().is_some_and(|ch| ch.is_alphabetic())
+           && chars.all(|ch| ch.is_alphanumeric() || ch == '_');
+  if !valid {
+    return Err(format!("{} '{}' doesn't have a valid name. Names need to start with a letter, \
+                        followed by letters, digits and underscores.", kind, name));
+  }
+  Ok(())
+}
+
+/// The fields of a layout for a register, from the lowest bits up. Any bits
+/// without a field get a field called rsvd followed by its lowest bit, with
+/// underscores after it if the register already has a field by that name.
+fn layout_fields(name: &str, register: &Register) -> Result<Vec<(String, u128)>, String> {
+  let mut fields: Vec<&Field> = register.fields.iter().collect();
+  fields.sort_by_key(|field| field.lo);
+  let reserved = |lo: u32| {
+    let mut name = format!("rsvd{}", lo);
+    while register.fields.iter().any(|field| field.name == name) {
+      name.push('_');
+    }
+    name
+  };
+  let mut layout = Vec::new();
+  let mut next = 0;
+  for field in fields {
+    if field.lo < next {
+      return Err(format!("Field {} of {} overlaps another field.", field.name, name));
+    }
+    if field.lo > next {
+      layout.push((reserved(next), u128::from(field.lo - next)));
+    }
+    layout.push((field.name.clone(), u128::from(field.bits)));
+    next = field.lo + field.bits;
+  }
+  if next > register.size {
+    return Err(format!("The fields of {} don't fit in its {} bits.", name, register.size));
+  }
+  if next < register.size {
+    layout.push((reserved(next), u128::from(register.size - next)));
+  }
+  Ok(layout)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SVD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<device>
+  <name>TEST</name>
+  <size>32</size>
+  <resetValue>0x00000000</resetValue>
+  <peripherals>
+    <peripheral>
+      <name>UART0</name>
+      <baseAddress>0x40011000</baseAddress>
+      <size>16</size>
+      <registers>
+        <register>
+          <name>CR</name>
+          <addressOffset>0x0C</addressOffset>
+          <resetValue>0x0300</resetValue>
+          <fields>
+            <field><name>EN</name><bitOffset>0</bitOffset><bitWidth>1</bitWidth></field>
+            <field><name>MODE</name><lsb>4</lsb><msb>6</msb></field>
+            <field><name>DIV</name><bitRange>[11:8]</bitRange></field>
+          </fields>
+        </register>
+        <register>
+          <name>DR</name>
+          <addressOffset>0x10</addressOffset>
+          <size>8</size>
+        </register>
+      </registers>
+    </peripheral>
+    <peripheral derivedFrom="UART0">
+      <name>UART1</name>
+      <baseAddress>0x40012000</baseAddress>
+    </peripheral>
+  </peripherals>
+</device>"#;
+
+  const JSON: &str = r#"{ "peripherals": [
+    { "name": "GPIO", "base": "0x48000000", "registers": [
+        { "name": "MODER", "offset": 0, "size": 16, "reset": "0xA800", "fields": [
+            { "name": "rsvd0", "offset": 2, "width": 2 },
+            { "name": "MODE1", "offset": 4, "width": 2 } ] },
+        { "name": "IDR", "offset": 16 } ] } ] }"#;
+
+  /// Find a register of a peripheral.
+  fn register<'a>(device: &'a Device, peripheral: &str, name: &str) -> &'a Register {
+    device.peripherals.iter()
+                      .find(|p| p.name == peripheral)
+                      .and_then(|p| p.registers.iter().find(|r| r.name == name))
+                      .unwrap()
+  }
+
+  /// The name, lowest bit and number of bits of each field.
+  fn fields(register: &Register) -> Vec<(&str, u32, u32)> {
+    register.fields.iter().map(|f| (f.name.as_str(), f.lo, f.bits)).collect()
+  }
+
+  #[test]
+  fn svd_fields() {
+    let device = parse_svd(SVD).unwrap();
+    let cr = register(&device, "UART0", "CR");
+    assert_eq!((cr.offset, cr.size, cr.reset), (0x0C, 16, 0x0300));
+    assert_eq!(fields(cr), vec![("EN", 0, 1), ("MODE", 4, 3), ("DIV", 8, 4)]);
+  }
+
+  #[test]
+  fn svd_inherits_size_and_reset_value() {
+    let device = parse_svd(SVD).unwrap();
+    let dr = register(&device, "UART0", "DR");
+    assert_eq!((dr.size, dr.reset), (8, 0));
+  }
+
+  #[test]
+  fn svd_derived_peripherals() {
+    let device = parse_svd(SVD).unwrap();
+    assert_eq!(device.peripherals[1].base, 0x40012000);
+    let cr = register(&device, "UART1", "CR");
+    assert_eq!(fields(cr), vec![("EN", 0, 1), ("MODE", 4, 3), ("DIV", 8, 4)]);
+    let missing = SVD.replace("derivedFrom=\"UART0\"", "derivedFrom=\"UART9\"");
+    assert!(parse_svd(&missing).is_err());
+  }
+
+  #[test]
+  fn json_registers() {
+    let device = parse_json(JSON).unwrap();
+    assert_eq!(device.peripherals[0].base, 0x48000000);
+    let moder = register(&device, "GPIO", "MODER");
+    assert_eq!((moder.offset, moder.size, moder.reset), (0, 16, 0xA800));
+    assert_eq!(fields(moder), vec![("rsvd0", 2, 2), ("MODE1", 4, 2)]);
+    let idr = register(&device, "GPIO", "IDR");
+    assert_eq!((idr.offset, idr.size, idr.reset), (16, 32, 0));
+    assert!(idr.fields.is_empty());
+  }
+
+  #[test]
+  fn reserved_bits() {
+    let device = parse_svd(SVD).unwrap();
+    let cr = register(&device, "UART0", "CR");
+    assert_eq!(layout_fields("UART0_CR", cr).unwrap(),
+               vec![("EN".to_string(), 1), ("rsvd1".to_string(), 3), ("MODE".to_string(), 3),
+                    ("rsvd7".to_string(), 1), ("DIV".to_string(), 4), ("rsvd12".to_string(), 4)]);
+
+    // A field which is really called rsvd0 keeps its name.
+    let device = parse_json(JSON).unwrap();
+    let moder = register(&device, "GPIO", "MODER");
+    assert_eq!(layout_fields("GPIO_MODER", moder).unwrap(),
+               vec![("rsvd0_".to_string(), 2), ("rsvd0".to_string(), 2), ("MODE1".to_string(), 2),
+                    ("rsvd6".to_string(), 10)]);
+  }
+
+  #[test]
+  fn bad_fields() {
+    let overlapping = JSON.replace("\"offset\": 4, \"width\": 2", "\"offset\": 3, \"width\": 2");
+    let device = parse_json(&overlapping).unwrap();
+    assert!(layout_fields("GPIO_MODER", register(&device, "GPIO", "MODER")).is_err());
+
+    let too_big = JSON.replace("\"offset\": 4, \"width\": 2", "\"offset\": 15, \"width\": 2");
+    let device = parse_json(&too_big).unwrap();
+    assert!(layout_fields("GPIO_MODER", register(&device, "GPIO", "MODER")).is_err());
+
+    let twice = JSON.replace("\"MODE1\"", "\"rsvd0\"");
+    let device = parse_json(&twice).unwrap();
+    let mut ctx = Context::new(Width::new(32).unwrap(), false);
+    assert!(device.define(&mut ctx).is_err());
+    assert!(ctx.variables().is_empty());
+  }
+
+  #[test]
+  fn define_constants_and_layouts() {
+    let device = parse_svd(SVD).unwrap();
+    let mut ctx = Context::new(Width::new(16).unwrap(), false);
+    assert!(device.define(&mut ctx).is_err());
+    assert!(ctx.variables().is_empty());
+
+    let mut ctx = Context::new(Width::new(32).unwrap(), false);
+    assert_eq!(device.define(&mut ctx), Ok(4));
+    assert_eq!(ctx.lookup("UART1_CR_Addr").unwrap(), 0x4001200C);
+    assert_eq!(ctx.lookup("UART0_CR_MODE_Pos").unwrap(), 4);
+    assert_eq!(ctx.lookup("UART0_CR_MODE").unwrap(), 0x70);
+    assert_eq!(ctx.layout_of("UART0_CR").unwrap().bits(), 16);
+    assert!(ctx.layout("UART0_DR").is_none());
+  }
+
+}
@@ -0,0 +1,622 @@
+
+use Error;
+use ast::{Expr, ExprKind, Prog};
+use builtins;
+use display::{Display, Format, Grouping};
+use eval::{Context, Overflow, Step, eval};
+use float;
+use layout::Layout;
+use lexer::{self, Token};
+use regmap;
+use span::Span;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use width::{Width, WIDTHS};
+
+/// Parse a width given by the user, or explain which widths are allowed.
+pub fn parse_width(s: &str) -> Result<Width, String> {
+  match Width::parse(s) {
+    Some(width) => Ok(width),
+    None => Err(format!("Invalid width '{}'. Expected one of {:?}.", s.trim(), WIDTHS)),
+  }
+}
+
+/// Parse an output format given by the user.
+fn parse_format(s: &str) -> Result<Format, String> {
+  match Format::parse(s) {
+    Some(format) => Ok(format),
+    None => Err(format!("Invalid format '{}'. Expected one of bin, hex, oct, dec, signed, char, \
+                         f16, bf16, f32 or f64.",
+                        s.trim())),
+  }
+}
+
+/// Parse a grouping of binary digits given by the user.
+fn parse_grouping(s: &str) -> Result<Grouping, String> {
+  match Grouping::parse(s) {
+    Some(grouping) => Ok(grouping),
+    None => Err(format!("Invalid grouping '{}'. Expected one of none, nibble or byte.", s.trim())),
+  }
+}
+
+/// Split suffixes giving a format, such as ':hex', and naming a layout, such
+/// as ':STATUS', off the end of an expression. The format comes last if
+/// there are both. A colon only starts a suffix if it doesn't end a
+/// conditional, so 'c ? a : STATUS' is left alone. If there are no suffixes,
+/// the input is returned unchanged.
+pub fn split_suffixes<'a>(input: &'a str, ctx: &Context) -> (&'a str, Option<Format>, Option<String>) {
+  let mut tokens = match lexer::lex(input) {
+    Ok(tokens) => tokens,
+    Err(_) => return (input, None, None),
+  };
+  let (mut end, mut format, mut layout) = (input.len(), None, None);
+  while tokens.len() >= 2 {
+    let name = match (&tokens[tokens.len() - 2].0, &tokens[tokens.len() - 1].0) {
+      (&Token::Colon, Token::Ident(name)) => name.clone(),
+      _ => break,
+    };
+    let colon = tokens[tokens.len() - 2].1;
+    tokens.truncate(tokens.len() - 2);
+    if ends_conditional(&tokens) {
+      break;
+    }
+    match Format::parse(&name) {
+      Some(f) if format.is_none() && layout.is_none() => format = Some(f),
+      _ if layout.is_none() && ctx.layout(&name).is_some() => layout = Some(name),
+      _ => break,
+    }
+    end = colon.start;
+  }
+  (input[..end].trim_end(), format, layout)
+}
+
+/// Check if a colon following these tokens would end a conditional, because
+/// there's a '?' outside of any brackets which no colon has matched yet.
+fn ends_conditional(tokens: &[(Token, Span)]) -> bool {
+  let mut depth = 0;
+  let mut pending = 0;
+  for (token, _) in tokens {
+    match *token {
+      Token::LeftParen | Token::LeftBracket | Token::LeftBrace => depth += 1,
+      Token::RightParen | Token::RightBracket | Token::RightBrace => depth -= 1,
+      Token::Question if depth == 0 => pending += 1,
+      Token::Colon if depth == 0 && pending > 0 => pending -= 1,
+      _ => (),
+    }
+  }
+  pending > 0
+}
+
+/// Parse an overflow policy given by the user.
+pub fn parse_overflow(s: &str) -> Result<Overflow, String> {
+  match Overflow::parse(s) {
+    Some(overflow) => Ok(overflow),
+    None => Err(format!("Invalid overflow policy '{}'. Expected one of wrapping, checked or saturating.",
+                        s.trim())),
+  }
+}
+
+/// Parse the name of a file given by the user.
+fn parse_path(s: &str) -> Result<&str, String> {
+  match s.trim() {
+    "" => Err("Expected the name of a file.".to_string()),
+    path => Ok(path),
+  }
+}
+
+/// Parse an on/off switch given by the user.
+fn parse_switch(s: &str) -> Result<bool, String> {
+  match s.trim() {
+    "on" => Ok(true),
+    "off" => Ok(false),
+    s => Err(format!("Expected 'on' or 'off' but found '{}'.", s)),
+  }
+}
+
+/// Describe how numbers are currently being displayed.
+pub fn describe_words(ctx: &Context) -> String {
+  let sign = if ctx.signed() { "signed" } else { "unsigned" };
+  format!("Numbers are displayed as {}-bit {} integers.", ctx.width(), sign)
+}
+
+/// Write out each step of an evaluation as rows of binary, with the operator
+/// to the left of its operands, and the results lined up underneath.
+fn show_steps(ctx: &Context, display: &Display, steps: &[Step], out: &mut Vec<String>) {
+  let word = |x| ::format(ctx, display, x);
+  for step in steps {
+    if let Some(ruler) = display.ruler(ctx.width()) {
+      out.push(format!("{:>3} {}", "", ruler));
+    }
+    match *step {
+      Step::Unary(op, e, result) => {
+        out.push(format!("{:>3} {}", format!("{:?}", op), word(e)));
+        out.push(format!("{:>3} {}", "=", word(result)));
+      },
+      Step::Binary(op, e1, e2, result) => {
+        out.push(format!("{:>3} {}", "", word(e1)));
+        out.push(format!("{:>3} {}", format!("{:?}", op), word(e2)));
+        out.push(format!("{:>3} {}", "=", word(result)));
+      },
+      Step::Settled(op, e1, result) => {
+        out.push(format!("{:>3} {}", "", word(e1)));
+        out.push(format!("{:>3} (not evaluated)", format!("{:?}", op)));
+        out.push(format!("{:>3} {}", "=", word(result)));
+      },
+      Step::Cond(cond, result) => {
+        out.push(format!("{:>3} {}", "?", word(cond)));
+        out.push(format!("{:>3} {}", "=", word(result)));
+      },
+      Step::Call(name, ref args, result) => {
+        out.push(format!("{:>3} {}", "", name));
+        for &arg in args {
+          out.push(format!("{:>3} {}", "", word(arg)));
+        }
+        out.push(format!("{:>3} {}", "=", word(result)));
+      },
+    }
+    out.push(String::new());
+  }
+}
+
+/// Write out the result of an evaluation after a label, with a ruler above it
+/// if it's on.
+fn show_result(ctx: &Context, display: &Display, label: &str, result: u128, out: &mut Vec<String>) {
+  if let Some(ruler) = display.ruler(ctx.width()) {
+    out.push(format!("{}{}", " ".repeat(label.len()), ruler));
+  }
+  out.push(format!("{}{}", label, ::format(ctx, display, result)));
+}
+
+/// Write out the fields of a word decoded with a layout, lined up under a
+/// label.
+fn show_decoded(ctx: &Context, display: &Display, layout: &Layout, label: &str, result: u128,
+                out: &mut Vec<String>) {
+  let indent = " ".repeat(label.len());
+  match layout.fits(ctx.width()) {
+    Ok(()) => for row in display.decode(result, ctx.width(), layout) {
+      out.push(format!("{}{}", indent, row));
+    },
+    Err(msg) => out.push(format!("{}{}", indent, msg)),
+  }
+}
+
+/// Something that went wrong while running a line of input.
+#[derive(Debug)]
+pub enum Failure {
+  Calc(Error),
+  Command(String),
+  /// A line of a file being loaded went wrong.
+  Load { path: String, line: usize, input: String, cause: Box<Failure> },
+}
+
+impl Failure {
+
+  /// The part of the input where things went wrong, if it's known.
+  pub fn span(&self) -> Option<Span> {
+    match *self {
+      Failure::Calc(ref e) => e.span(),
+      Failure::Command(_) | Failure::Load { .. } => None,
+    }
+  }
+
+  /// Describe what went wrong. If it's known where, the input is shown with
+  /// that part underlined.
+  pub fn report(&self, input: &str) -> String {
+    if let Failure::Load { ref path, line, ref input, ref cause } = *self {
+      return format!("{}:{}: {}", path, line, cause.report(input));
+    }
+    match self.span() {
+      Some(span) => format!("Error: {}\n{}", self, span.underline(input.trim())),
+      None => format!("Error: {}", self),
+    }
+  }
+
+}
+
+impl fmt::Display for Failure {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Failure::Calc(ref e) => write!(f, "{}", e),
+      Failure::Command(ref msg) => write!(f, "{}", msg),
+      Failure::Load { ref path, line, ref cause, .. } => write!(f, "{}:{}: {}", path, line, cause),
+    }
+  }
+}
+
+/// Every command, and what it does, as listed by ':help'.
+pub const COMMANDS: [(&str, &str); 17] = [
+  (":width <bits>",       "Change the width of words."),
+  (":signed on|off",      "Treat words as signed or unsigned."),
+  (":overflow <policy>",  "Choose what happens on overflow: wrapping, checked or saturating."),
+  (":trace on|off",       "Show each step of the evaluation."),
+  (":format <format>",    "Show results in bin, hex, oct, dec, signed, char, f16, bf16, f32 or f64."),
+  (":group <grouping>",   "Group binary digits by none, nibble or byte."),
+  (":ruler on|off",       "Number the bits above binary results."),
+  (":vars",               "List every variable and its value."),
+  (":functions",          "List the builtin functions, float conversions and your own."),
+  (":layouts",            "List the layouts you've defined."),
+  (":unset <name>",       "Forget a variable, function or layout."),
+  (":clear",              "Forget every variable, function and layout."),
+  (":save <file>",        "Save the settings, variables, functions and layouts to a file."),
+  (":load <file>",        "Run each line of a file, such as one made by ':save'."),
+  (":import <file>",      "Define constants and layouts for the registers in an SVD or JSON file."),
+  (":history [n]",        "Show the lines entered so far, or only the last n."),
+  (":help",               "List the commands."),
+];
+
+/// Everything that lasts between one line of input and the next: the context
+/// that programs are evaluated in, the display settings and the history of
+/// input. When the session is interactive, commands confirm what they've done.
+/// Nothing but errors comes out while a file is being loaded.
+pub struct Session {
+  pub ctx: Context,
+  pub display: Display,
+  pub interactive: bool,
+  /// The lines that have been entered, oldest first.
+  pub history: Vec<String>,
+  /// The files being loaded, innermost last.
+  loading: Vec<String>,
+}
+
+impl Session {
+
+  /// Start a session which evaluates programs in a context, showing their
+  /// results with the default display settings.
+  pub fn new(ctx: Context) -> Session {
+    Session {
+      ctx,
+      display: Display::default(),
+      interactive: false,
+      history: Vec::new(),
+      loading: Vec::new(),
+    }
+  }
+
+  /// Add a message confirming a command to the output, if the session is
+  /// interactive.
+  fn confirm(&self, msg: &str, out: &mut Vec<String>) {
+    if self.interactive && self.loading.is_empty() {
+      out.push(msg.to_string());
+    }
+  }
+
+  /// Run a line of input entered by the user, remembering it in the history.
+  /// Returns the lines of output to show the user.
+  pub fn run(&mut self, input: &str) -> Result<Vec<String>, Failure> {
+    let input = input.trim();
+    if !input.is_empty() && !input.starts_with('#') {
+      self.history.push(input.to_string());
+    }
+    let mut out = Vec::new();
+    self.execute(input, &mut out)?;
+    Ok(out)
+  }
+
+  /// Run a line of input, which is either a command starting with ':' or a
+  /// program. Any results are added to the output. Blank lines and lines
+  /// starting with '#' are ignored. The spans of any errors are relative to
+  /// the input once it's been trimmed.
+  fn execute(&mut self, input: &str, out: &mut Vec<String>) -> Result<(), Failure> {
+    let input = input.trim();
+    if input.is_empty() || input.starts_with('#') {
+      return Ok(());
+    }
+    if input.starts_with(':') {
+      return self.run_command(input, out);
+    }
+
+    // An expression can end with a suffix giving the format of its result,
+    // and one naming a layout to decode it with.
+    let (input, format, suffix) = split_suffixes(input, &self.ctx);
+    let mut display = self.display;
+    if let Some(format) = format {
+      display.format = format;
+    }
+
+    // Parse the program.
+    let prog = ::parse(input).map_err(Failure::Calc)?;
+
+    // Show the result, if there is one.
+    let result = eval(&mut self.ctx, &prog).map_err(|e| Failure::Calc(Error::Eval(e)))?;
+    let steps = self.ctx.take_steps();
+    if !self.loading.is_empty() {
+      return Ok(());
+    }
+    show_steps(&self.ctx, &display, &steps, out);
+    match result {
+
+      // Remember the result, so that it can be used later as '_' or by its
+      // number. At the prompt, the result is shown with its number.
+      Some(result) => {
+        let n = self.ctx.remember(result);
+        let label = if self.interactive { format!("${} = ", n) } else { String::new() };
+        show_result(&self.ctx, &display, &label, result, out);
+
+        // Decode the result with the layout named by the suffix, or else the
+        // layout of the variable it came from.
+        let var = match prog {
+          Prog::Assign(ref name, _, _, _) | Prog::AssignSlice(ref name, _, _, _, _) => Some(name),
+          Prog::Expression(Expr { kind: ExprKind::Var(ref name), .. }) => Some(name),
+          _ => None,
+        };
+        let layout = match suffix {
+          Some(ref name) => self.ctx.layout(name),
+          None => var.and_then(|var| self.ctx.layout_of(var)),
+        };
+        if let Some(layout) = layout {
+          show_decoded(&self.ctx, &display, layout, &label, result, out);
+        }
+      },
+      None => match prog {
+        Prog::Function(ref name, ref params, _) => {
+          self.confirm(&format!("Defined {}({}).", name, params.join(", ")), out);
+        },
+        Prog::Layout(ref name, _) => {
+          let bits = self.ctx.layout(name).map_or(0, |layout| layout.bits());
+          self.confirm(&format!("Defined layout {} with {} bits.", name, bits), out);
+        },
+        _ => (),
+      },
+    }
+    Ok(())
+  }
+
+  /// Run a command which inspects or changes the session.
+  fn run_command(&mut self, input: &str, out: &mut Vec<String>) -> Result<(), Failure> {
+
+    // Change the word width.
+    if let Some(bits) = input.strip_prefix(":width") {
+      let width = parse_width(bits).map_err(Failure::Command)?;
+      self.ctx.set_width(width);
+      self.confirm(&describe_words(&self.ctx), out);
+    }
+
+    // Switch between signed and unsigned words.
+    else if let Some(switch) = input.strip_prefix(":signed") {
+      let signed = parse_switch(switch).map_err(Failure::Command)?;
+      self.ctx.set_signed(signed);
+      self.confirm(&describe_words(&self.ctx), out);
+    }
+
+    // Choose what happens when arithmetic overflows.
+    else if let Some(policy) = input.strip_prefix(":overflow") {
+      let overflow = parse_overflow(policy).map_err(Failure::Command)?;
+      self.ctx.set_overflow(overflow);
+      self.confirm(&format!("Arithmetic overflow is {}.", self.ctx.overflow()), out);
+    }
+
+    // Show or hide the steps of each evaluation.
+    else if let Some(switch) = input.strip_prefix(":trace") {
+      let tracing = parse_switch(switch).map_err(Failure::Command)?;
+      self.ctx.set_tracing(tracing);
+    }
+
+    // List the builtin functions, the float conversions and the user's
+    // functions.
+    else if input == ":functions" {
+      for builtin in builtins::BUILTINS.iter() {
+        out.push(format!("{:<10} {}", builtin.name, builtin.doc));
+      }
+      for conversion in float::CONVERSIONS.iter() {
+        out.push(format!("{:<10} {}", conversion.name, conversion.doc));
+      }
+      for (name, func) in self.ctx.functions() {
+        out.push(format!("fn {}({}) = {}", name, func.params.join(", "), func.body));
+      }
+    }
+
+    // List every variable and its value.
+    else if input == ":vars" {
+      for (name, val) in self.ctx.variables() {
+        let layout = self.ctx.layout_of(name).map_or(String::new(), |layout| format!(": {}", layout.name));
+        out.push(format!("{}{} = {}", name, layout, ::format(&self.ctx, &self.display, val)));
+      }
+    }
+
+    // List the layouts the user has defined.
+    else if input == ":layouts" {
+      for layout in self.ctx.layouts() {
+        out.push(format!("{}", layout));
+      }
+    }
+
+    // Forget variables and functions.
+    else if let Some(name) = input.strip_prefix(":unset") {
+      let name = name.trim();
+      if name.is_empty() {
+        return Err(Failure::Command("Expected the name of a variable or function to unset.".to_string()));
+      }
+      if !self.ctx.unset(name) {
+        return Err(Failure::Command(format!("There's no variable or function called '{}'.", name)));
+      }
+      self.confirm(&format!("Unset {}.", name), out);
+    }
+    else if input == ":clear" {
+      self.ctx.clear();
+      self.confirm("Unset every variable, function and layout.", out);
+    }
+
+    // Change how results are displayed.
+    else if let Some(format) = input.strip_prefix(":format") {
+      self.display.format = parse_format(format).map_err(Failure::Command)?;
+    }
+    else if let Some(grouping) = input.strip_prefix(":group") {
+      self.display.grouping = parse_grouping(grouping).map_err(Failure::Command)?;
+    }
+    else if let Some(switch) = input.strip_prefix(":ruler") {
+      self.display.ruler = parse_switch(switch).map_err(Failure::Command)?;
+    }
+
+    // Show the lines entered so far, or only the last few.
+    else if let Some(count) = input.strip_prefix(":history") {
+      let count = match count.trim() {
+        "" => self.history.len(),
+        count => count.parse::<usize>().map_err(|_| {
+          Failure::Command(format!("Expected a number of lines but found '{}'.", count))
+        })?,
+      };
+      let start = self.history.len().saturating_sub(count);
+      for (i, line) in self.history.iter().enumerate().skip(start) {
+        out.push(format!("{:>5}  {}", i + 1, line));
+      }
+    }
+
+    // Save the session to a file, or load one.
+    else if let Some(path) = input.strip_prefix(":save") {
+      let path = parse_path(path).map_err(Failure::Command)?;
+      let mut file = File::create(path).map_err(|e| Failure::Command(format!("{}: {}", path, e)))?;
+      file.write_all(self.dump().as_bytes())
+        .map_err(|e| Failure::Command(format!("{}: {}", path, e)))?;
+      self.confirm(&format!("Saved the session to {}.", path), out);
+    }
+    else if let Some(path) = input.strip_prefix(":load") {
+      let path = parse_path(path).map_err(Failure::Command)?;
+      self.load(path)?;
+      self.confirm(&format!("Loaded {}.", path), out);
+    }
+
+    // Define constants and layouts for the registers of a device.
+    else if let Some(path) = input.strip_prefix(":import") {
+      let path = parse_path(path).map_err(Failure::Command)?;
+      let count = regmap::read(path)
+        .and_then(|device| device.define(&mut self.ctx))
+        .map_err(|e| Failure::Command(format!("{}: {}", path, e)))?;
+      self.confirm(&format!("Imported {} register(s) from {}.", count, path), out);
+    }
+
+    else if input == ":help" {
+      for &(usage, doc) in COMMANDS.iter() {
+        out.push(format!("{:<20} {}", usage, doc));
+      }
+    }
+
+    else {
+      let name = input.split_whitespace().next().unwrap_or(input);
+      return Err(Failure::Command(format!("Unknown command '{}'. Type ':help' to list the commands.", name)));
+    }
+    Ok(())
+  }
+
+  /// Write out the settings, variables and functions of the session as lines
+  /// of input which recreate them when they're loaded.
+  pub fn dump(&self) -> String {
+    let ctx = &self.ctx;
+    let switch = |on: bool| if on { "on" } else { "off" };
+    let mut lines = vec![
+      "# A bitshift session. Load it with ':load <file>'.".to_string(),
+      format!(":width {}", ctx.width()),
+      format!(":signed {}", switch(ctx.signed())),
+      format!(":overflow {}", ctx.overflow()),
+      format!(":trace {}", switch(ctx.tracing())),
+      format!(":format {}", self.display.format),
+      format!(":group {}", self.display.grouping),
+      format!(":ruler {}", switch(self.display.ruler)),
+    ];
+    for layout in ctx.layouts() {
+      lines.push(format!("{}", layout));
+    }
+    for (name, val) in ctx.variables() {
+      match ctx.layout_of(name) {
+        Some(layout) => lines.push(format!("let {}: {} = {:#x}", name, layout.name, val)),
+        None => lines.push(format!("let {} = {:#x}", name, val)),
+      }
+    }
+    for (name, func) in ctx.functions() {
+      lines.push(format!("fn {}({}) = {}", name, func.params.join(", "), func.body));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+  }
+
+  /// Run each line of a file quietly, stopping at the first error.
+  pub fn load(&mut self, path: &str) -> Result<(), Failure> {
+    if self.loading.iter().any(|loading| loading == path) {
+      return Err(Failure::Command(format!("{} loads itself.", path)));
+    }
+    let mut text = String::new();
+    File::open(path).and_then(|mut file| file.read_to_string(&mut text))
+      .map_err(|e| Failure::Command(format!("{}: {}", path, e)))?;
+    self.restore(path, &text)
+  }
+
+  /// Run each line of some text quietly, such as the output of dump, stopping
+  /// at the first error. Errors are reported as coming from the file path.
+  pub fn restore(&mut self, path: &str, text: &str) -> Result<(), Failure> {
+    self.loading.push(path.to_string());
+    let mut result = Ok(());
+    for (i, line) in text.lines().enumerate() {
+      if let Err(cause) = self.execute(line, &mut Vec::new()) {
+        result = Err(Failure::Load {
+          path: path.to_string(),
+          line: i + 1,
+          input: line.to_string(),
+          cause: Box::new(cause),
+        });
+        break;
+      }
+    }
+    self.loading.pop();
+    result
+  }
+
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A session with 16-bit words, which shows results in decimal.
+  fn session() -> Session {
+    let mut session = Session::new(Context::new(Width::new(16).unwrap(), false));
+    session.display.format = Format::Decimal;
+    session
+  }
+
+  #[test]
+  fn suffixes() {
+    let mut session = session();
+    assert_eq!(session.run("7 :hex").unwrap(), vec!["0x0007 (7)"]);
+    assert_eq!(session.run("let hex = 2").unwrap(), vec!["2"]);
+    assert_eq!(session.run("0 ? 1 : hex").unwrap(), vec!["2"]);
+    assert_eq!(session.run("(0 ? 1 : hex) :hex").unwrap(), vec!["0x0002 (2)"]);
+    session.run("layout S { lo:4, hi:12 }").unwrap();
+    assert_eq!(session.run("0x123 :S :hex").unwrap(),
+               vec!["0x0123 (291)", "hi=18       lo=3", "0000000100100011"]);
+    session.run("let S = 5").unwrap();
+    assert_eq!(session.run("0 ? 1 : S").unwrap(), vec!["5"]);
+  }
+
+  #[test]
+  fn save_and_restore() {
+    let mut saved = session();
+    for line in &[":signed on", ":overflow checked", ":format hex", ":group nibble",
+                  "layout S { lo:4, mid:4, hi:8 }", "let x = -2", "let v: S = -0x1234",
+                  "fn f(a, b) = a < b ? -a : a ** 2 % 7", "fn g(a) = !a >>> 1 && f(a, 3)"] {
+      saved.run(line).unwrap();
+    }
+    let mut restored = session();
+    restored.restore("saved", &saved.dump()).unwrap();
+    assert_eq!(restored.dump(), saved.dump());
+    for line in &["x", "v", "v.hi", "{v.mid, v.lo}", "f(x, 1)", "f(5, 1)", "g(x)", "0x7fff + 1"] {
+      assert_eq!(restored.run(line).map_err(|e| e.to_string()),
+                 saved.run(line).map_err(|e| e.to_string()));
+    }
+  }
+
+  #[test]
+  fn layouts_which_no_longer_fit() {
+    let mut saved = session();
+    for line in &["layout S { lo:8, hi:8 }", "let v: S = 0x1234", ":width 8"] {
+      saved.run(line).unwrap();
+    }
+    assert!(saved.run("v.hi").is_err());
+    assert!(saved.run("let w: S = 1").is_err());
+    let mut restored = session();
+    restored.restore("saved", &saved.dump()).unwrap();
+    assert_eq!(restored.run("v").unwrap(), vec!["52"]);
+    restored.run(":width 16").unwrap();
+    assert!(restored.run("v.hi").is_err());
+  }
+
+}
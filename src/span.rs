@@ -0,0 +1,36 @@
+
+/// A range of bytes in the input, from start up to but not including end.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+}
+
+impl Span {
+
+  pub fn new(start: usize, end: usize) -> Span {
+    Span { start, end }
+  }
+
+  /// The smallest span covering both this span and the other one.
+  pub fn to(&self, other: Span) -> Span {
+    Span::new(self.start.min(other.start), self.end.max(other.end))
+  }
+
+  /// Show the input with this span underlined by carets, e.g.
+  ///
+  /// ```text
+  /// x + foo
+  ///     ^^^
+  /// ```
+  ///
+  /// Each line is indented by two spaces.
+  pub fn underline(&self, input: &str) -> String {
+    let start = self.start.min(input.len());
+    let end = self.end.max(start).min(input.len());
+    let before = input[..start].chars().count();
+    let length = input[start..end].chars().count().max(1);
+    format!("  {}\n  {}{}", input, " ".repeat(before), "^".repeat(length))
+  }
+
+}
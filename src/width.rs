@@ -0,0 +1,111 @@
+
+use std::fmt;
+
+/// The number of bits in a word. Every value is stored in a u128, but only the
+/// low `bits` bits are significant.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Width {
+  bits: u32,
+}
+
+/// The word widths that can be selected.
+pub const WIDTHS: [u32; 5] = [8, 16, 32, 64, 128];
+
+impl Width {
+
+  /// Make a width of the given number of bits. Returns None unless bits is one
+  /// of the supported WIDTHS.
+  pub fn new(bits: u32) -> Option<Width> {
+    if WIDTHS.contains(&bits) {
+      Some(Width { bits })
+    } else {
+      None
+    }
+  }
+
+  /// Parse a width such as "32".
+  pub fn parse(s: &str) -> Option<Width> {
+    s.trim().parse::<u32>().ok().and_then(Width::new)
+  }
+
+  pub fn bits(&self) -> u32 {
+    self.bits
+  }
+
+  /// A mask with a 1 in every bit of the word.
+  pub fn mask(&self) -> u128 {
+    if self.bits >= 128 {
+      u128::MAX
+    } else {
+      (1 << self.bits) - 1
+    }
+  }
+
+  /// Discard any bits above the top of the word.
+  pub fn truncate(&self, x: u128) -> u128 {
+    x & self.mask()
+  }
+
+  /// Check if x can be stored in a word without losing any bits.
+  pub fn fits(&self, x: u128) -> bool {
+    x & !self.mask() == 0
+  }
+
+  /// Rotate the bits of a word left by n places. Bits shifted off the top of
+  /// the word come back in at the bottom.
+  pub fn rotate_left(&self, x: u128, n: u128) -> u128 {
+    let n = (n % u128::from(self.bits)) as u32;
+    if n == 0 {
+      x
+    } else {
+      self.truncate(x << n | x >> (self.bits - n))
+    }
+  }
+
+  /// Rotate the bits of a word right by n places. Bits shifted off the bottom
+  /// of the word come back in at the top.
+  pub fn rotate_right(&self, x: u128, n: u128) -> u128 {
+    let n = (n % u128::from(self.bits)) as u32;
+    self.rotate_left(x, u128::from(self.bits - n))
+  }
+
+  /// Interpret a word as a two's complement signed integer.
+  pub fn sign_extend(&self, x: u128) -> i128 {
+    let shift = 128 - self.bits;
+    ((x << shift) as i128) >> shift
+  }
+
+  /// The smallest signed integer that fits in a word.
+  pub fn signed_min(&self) -> i128 {
+    i128::MIN >> (128 - self.bits)
+  }
+
+  /// The largest signed integer that fits in a word.
+  pub fn signed_max(&self) -> i128 {
+    i128::MAX >> (128 - self.bits)
+  }
+
+  /// Check if x can be stored as a two's complement word without overflowing.
+  pub fn fits_signed(&self, x: i128) -> bool {
+    self.signed_min() <= x && x <= self.signed_max()
+  }
+
+  /// Store a signed integer as a two's complement word, discarding any bits
+  /// that don't fit.
+  pub fn wrap_signed(&self, x: i128) -> u128 {
+    self.truncate(x as u128)
+  }
+
+}
+
+impl Default for Width {
+  fn default() -> Width {
+    Width { bits: 16 }
+  }
+}
+
+impl fmt::Display for Width {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.bits)
+  }
+}